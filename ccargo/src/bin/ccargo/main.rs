@@ -1,9 +1,7 @@
 use ccargo::core::*;
-use ccargo::cc::{Profile, Toolchain};
-use ccargo::toml::read_package;
-use ccargo::utils::IResult;
-use std::collections::HashSet;
-use std::path::{Path, PathBuf};
+use ccargo::cc::Toolchain;
+use ccargo::toml::load_packages;
+use ccargo::reproducible;
 
 // SDK
 //      name
@@ -15,44 +13,6 @@ use std::path::{Path, PathBuf};
 //      install
 
 
-/// Load all
-pub fn load_packages(
-    path: &Path,
-    config: &Config,
-) -> IResult<(Package, PackageMap)> {
-    // TODO: Verify all shared targets have a unique name
-    // TODO: Verify there are no runtime lib conflicts
-    // TODO: Verify that target dependencies are only libraries
-    // TODO: Verify all files exist or are step outputs
-    // TODO: Verify every bin target that is depended on by other units has no dylib dependencies itself
-    // TODO: Recursively load CCargo.toml files inside package root dir
-    let mut packages = Vec::new();
-    load_packages_recursive(path, config, &mut packages, &mut HashSet::new())?;
-    Ok((
-        // first package is the root
-        packages.first().unwrap().clone(), 
-        PackageMap::from_packages(&packages)
-    ))
-}
-
-fn load_packages_recursive(
-    path: &Path,
-    config: &Config,
-    packages: &mut Vec<Package>,
-    seen: &mut HashSet<PathBuf>,
-) -> IResult<()> {
-    if !seen.insert(path.to_path_buf()) {
-        return Ok(());
-    }
-    let pkg = read_package(path, config)?;
-    packages.push(pkg.clone());
-    for dep in pkg.dependencies.iter() {
-        load_packages_recursive(&dep.source_id.manifest_path(), config, packages, seen)?;
-    }
-    Ok(())
-}
-
-
 use std::time::{Instant, Duration};
 
 struct Timer {
@@ -78,22 +38,57 @@ impl Timer {
 }
 
 
+/// Command line arguments
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    // build from two copies of the sources and compare the outputs instead of running
+    verify_reproducible: bool,
+}
+
+impl Args {
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for (i, arg) in args.into_iter().enumerate() {
+            match arg.as_str() {
+                "build" if i == 0 => {}
+                "--verify-reproducible" => parsed.verify_reproducible = true,
+                _ => return Err(format!("unexpected argument `{arg}`")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+
 fn main() {
     let root = std::env::current_dir().unwrap().join("target/tmp");
     let path = root.join("CCargo.toml");
     
     let mut timer = Timer::new();
     let config = Config::default().unwrap();
-    let profile = Profile::dev();
-    let layout = Layout::new(root, &profile, None);    
-    let (main_package, packages) = load_packages(&path, &config).unwrap();
-    let main_target = main_package.targets.last().unwrap();
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(v) => v,
+        Err(e) => {
+            drop(config.shell().error(e));
+            std::process::exit(1);
+        }
+    };
     let toolchain = Toolchain::default().unwrap();
+    let (main_package, packages) = load_packages(&path, &config, toolchain.target()).unwrap();
+    let profile = main_package.profile("dev").unwrap();
+    let layout = Layout::new(&root, &profile, None);    
+    let main_target = main_package.targets.last().unwrap();
     timer.print_elapsed("Load");
 
     for warning in main_package.warnings.iter() {
         drop(config.shell().warn(warning));
     }
+
+    if args.verify_reproducible {
+        verify_reproducible_build(&config, &toolchain, &root);
+        timer.print_elapsed("Verify");
+        return;
+    }
     
     let cx = Context::new(
         &config,
@@ -103,7 +98,7 @@ fn main() {
         &packages, 
         &main_package,
         &["foo".to_string()],
-    );
+    ).unwrap();
     cx.compile().unwrap();
     timer.print_elapsed("Build");
     // println!("{:16} {:.3}s", "Compile", config.creation_time().elapsed().as_secs_f64());
@@ -115,6 +110,21 @@ fn main() {
     timer.print_elapsed("Run");
 }
 
+
+/// Build the selected targets from two copies of the sources and compare the outputs
+fn verify_reproducible_build(config: &Config, toolchain: &Toolchain, root: &std::path::Path) {
+    let differ = reproducible::verify(config, toolchain, root, "dev", &["foo".to_string()]).unwrap();
+    if differ.is_empty() {
+        println!("All outputs are reproducible");
+    } else {
+        for (name, files) in differ.iter() {
+            let files = files.iter().map(|v| format!("\n    {}", v.display())).collect::<String>();
+            drop(config.shell().error(format!("Target `{name}` is not reproducible, these files differ:{files}")));
+        }
+        std::process::exit(1);
+    }
+}
+
 // 1. Parse args/config
 //      - choose package/target
 //      - choose platform
@@ -0,0 +1,260 @@
+//! Entry point for building `CCargo.toml` packages from a Cargo build script
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     ccargo::build_script::build("native/CCargo.toml", &["libfoo"]).unwrap();
+//! }
+//! ```
+
+use crate::cc::{BinType, Toolchain};
+use crate::core::{Config, Context, Layout, Target, Unit, fingerprint::DepInfo};
+use crate::toml::load_packages;
+use crate::utils::{IResult, BinaryDeserialize, paths};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use anyhow::Context as _;
+
+
+/// Build the `targets` of the package at `manifest` (relative to the crate being built)
+/// and tell Cargo how to link them
+pub fn build(manifest: impl AsRef<Path>, targets: &[&str]) -> IResult<()> {
+    let manifest_dir = PathBuf::from(env("CARGO_MANIFEST_DIR")?);
+    let out_dir = PathBuf::from(env("OUT_DIR")?);
+    let target = env("TARGET")?;
+    let profile_name = if env("PROFILE")? == "release" { "release" } else { "dev" };
+    if let Some(jobs) = std::env::var("NUM_JOBS").ok().and_then(|v| v.parse().ok()) {
+        // fails if the pool was already initialized, which is fine
+        drop(rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global());
+    }
+
+    let path = paths::abs(manifest, &manifest_dir);
+    let config = Config::default()?;
+    let (main_package, packages) = load_packages(&path, &config, &target)?;
+    for warning in main_package.warnings.iter() {
+        println!("cargo:warning={warning}");
+    }
+
+    let profile = match main_package.profile(profile_name) {
+        Some(v) => v,
+        None => anyhow::bail!("profile `{profile_name}` is not defined"),
+    };
+    let toolchain = Toolchain::new(
+        &target,
+        std::env::var_os("CC").map(PathBuf::from),
+        std::env::var_os("CXX").map(PathBuf::from),
+    )?;
+    let layout = Layout::new(&out_dir, &profile, Some(&target));
+    let selected = targets.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+    let cx = Context::new(
+        &config,
+        &layout,
+        &toolchain,
+        &profile,
+        &packages,
+        &main_package,
+        &selected,
+    )?;
+    if cx.units.is_empty() {
+        anyhow::bail!("no targets to build in `{}`", path.display())
+    }
+    cx.compile()?;
+
+    for file in rerun_if_changed(&cx)? {
+        println!("cargo:rerun-if-changed={}", file.display());
+    }
+    println!("cargo:rerun-if-env-changed=CC");
+    println!("cargo:rerun-if-env-changed=CXX");
+
+    // Libraries are listed before the libraries they depend on
+    let mut libs = Vec::new();
+    let mut links = Vec::new();
+    let mut search_paths = Vec::new();
+    for target in main_package.targets.iter().filter(|t| selected.contains(&t.name.to_string())) {
+        if target.kind.has_output() {
+            libs.push(target.output_path(&layout, toolchain.target()));
+        }
+        let deps = &cx.target_deps[target];
+        libs.extend(deps.libs.iter().cloned());
+        links.extend(deps.links.iter().cloned());
+        search_paths.extend(deps.search_paths.iter().cloned());
+    }
+    dedup_last(&mut libs);
+    dedup_last(&mut links);
+
+    // Rustc only finds libraries with the platform's naming conventions,
+    // so they are copied into one directory with those names
+    let lib_dir = out_dir.join("lib");
+    paths::create_dir_all(&lib_dir)?;
+    println!("cargo:rustc-link-search=native={}", lib_dir.display());
+    for lib in libs.iter() {
+        let target = match cx.units.with_output(lib).and_then(Unit::as_target) {
+            Some(v) => v,
+            None => continue,
+        };
+        let (kind, name) = copy_for_rustc(target, lib, &lib_dir, toolchain.target())?;
+        println!("cargo:rustc-link-lib={kind}={name}");
+    }
+    for path in search_paths.iter().collect::<BTreeSet<_>>() {
+        println!("cargo:rustc-link-search=native={}", path.display());
+    }
+    for link in links.iter() {
+        println!("cargo:rustc-link-lib={link}");
+    }
+
+    Ok(())
+}
+
+
+fn env(key: &str) -> IResult<String> {
+    std::env::var(key)
+        .with_context(|| format!("environment variable `{key}` is not set, is this a build script?"))
+}
+
+
+// Manifests of all packages and all files in the dep-info of every unit that was built
+fn rerun_if_changed(cx: &Context) -> IResult<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let target_root = cx.layout.target();
+    for unit in cx.unit_graph.nodes() {
+        let pkg_root = unit.package().root();
+        let source_id = unit.package().source_id();
+        // pkg-config packages come from a `.pc` file
+        if source_id.path().is_file() {
+            files.insert(source_id.path().to_path_buf());
+        } else {
+            files.insert(source_id.manifest_path());
+        }
+
+        let dep_info_path = match unit {
+            Unit::Target(target) => {
+                files.extend(target.sources.iter().cloned());
+                target.dep_info_path(cx.layout)
+            }
+            Unit::Step(step) => {
+                files.extend(step.inputs.iter().cloned());
+                step.dep_info_path(cx.layout)
+            }
+        };
+        if !dep_info_path.exists() {
+            continue;
+        }
+        if let Some(dep_info) = DepInfo::from_bytes(&paths::read_bytes(&dep_info_path)?) {
+            files.extend(dep_info.files(pkg_root, &target_root));
+        }
+    }
+    Ok(files)
+}
+
+
+// Copy `lib` into `dir` with the name rustc expects, returns the kind and name used for linking
+fn copy_for_rustc(
+    target: &Target,
+    lib: &Path,
+    dir: &Path,
+    target_triple: &str,
+) -> IResult<(&'static str, String)> {
+    let msvc = target_triple.contains("msvc");
+    let stem = lib.file_stem().unwrap().to_string_lossy();
+    let name = if msvc { &stem } else { stem.strip_prefix("lib").unwrap_or(&stem) }.to_string();
+    let (kind, bin_type) = match BinType::from(target.kind) {
+        BinType::Shared => ("dylib", BinType::Shared),
+        _ => ("static", BinType::Static),
+    };
+    let prefix = if msvc { "" } else { "lib" };
+
+    let mut copies = vec![(lib.to_path_buf(), format!("{prefix}{name}.{}", bin_type.ext(target_triple)))];
+    // msvc links against the import library of a dll
+    if msvc && kind == "dylib" {
+        copies.push((lib.with_extension("lib"), format!("{name}.lib")));
+    }
+    // versioned shared libraries are loaded by their soname at runtime
+    if let Ok(soname) = std::fs::read_link(lib) {
+        copies.push((lib.to_path_buf(), soname.to_string_lossy().into_owned()));
+    }
+    for (src, file_name) in copies {
+        let dst = dir.join(file_name);
+        if let (Ok(from), Ok(to)) = (paths::mtime(&src), paths::mtime(&dst)) {
+            if to >= from {
+                continue;
+            }
+        }
+        std::fs::copy(&src, &dst)
+            .with_context(|| format!("failed to copy `{}` to `{}`", src.display(), dst.display()))?;
+    }
+    Ok((kind, name))
+}
+
+
+// Remove duplicates, keeping the last occurrence so dependencies stay after their dependents
+fn dedup_last<T: Ord + Clone>(v: &mut Vec<T>) {
+    let mut seen = BTreeSet::new();
+    v.reverse();
+    v.retain(|x| seen.insert(x.clone()));
+    v.reverse();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toml::CCARGO_TOML;
+
+    #[test]
+    fn dedup_keeps_last() {
+        let mut v = vec!["a", "b", "c", "b", "d", "a"];
+        dedup_last(&mut v);
+        assert_eq!(v, ["c", "b", "d", "a"]);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn rerun_and_copy_libraries() {
+        let root = std::env::temp_dir().join("ccargo-test-build-script");
+        drop(std::fs::remove_dir_all(&root));
+        paths::write_create_all(root.join("src").join("a.h"), "int a(void);\n").unwrap();
+        paths::write(root.join("src").join("a.c"), "#include \"a.h\"\nint a(void) { return 1; }\n").unwrap();
+        paths::write(root.join("src").join("b.c"), "int b(void) { return 2; }\n").unwrap();
+        paths::write(root.join(CCARGO_TOML), r#"
+[package]
+name = "bs"
+version = "0.1.0"
+
+[[lib]]
+name = "a"
+sources = ["src/a.c"]
+
+[[lib]]
+name = "b"
+sources = ["src/b.c"]
+shared = true
+"#).unwrap();
+        let config = Config::default().unwrap();
+        let toolchain = Toolchain::default().unwrap();
+        let triple = toolchain.target().to_string();
+        let (package, packages) = load_packages(&root.join(CCARGO_TOML), &config, &triple).unwrap();
+        let profile = package.profile("dev").unwrap();
+        let layout = Layout::new(&root, &profile, None);
+        let selected = ["a".to_string(), "b".to_string()];
+        let cx = Context::new(&config, &layout, &toolchain, &profile, &packages, &package, &selected).unwrap();
+        cx.compile().unwrap();
+
+        // the manifest, the sources and the headers they include
+        let files = rerun_if_changed(&cx).unwrap();
+        for file in ["src/a.h", "src/a.c", "src/b.c", CCARGO_TOML] {
+            assert!(files.iter().any(|v| v.ends_with(file)), "`{file}` is missing in {files:?}");
+        }
+
+        let lib_dir = root.join("rustc");
+        paths::create_dir_all(&lib_dir).unwrap();
+        let copy = |name: &str| {
+            let target = package.targets.iter().find(|t| t.name.to_string() == name).unwrap();
+            copy_for_rustc(target, &target.output_path(&layout, &triple), &lib_dir, &triple).unwrap()
+        };
+        assert_eq!(copy("a"), ("static", "a".to_string()));
+        assert_eq!(copy("b"), ("dylib", "b".to_string()));
+        assert!(lib_dir.join("liba.a").is_file());
+        assert!(lib_dir.join("libb.so").is_file());
+        drop(std::fs::remove_dir_all(&root));
+    }
+}
@@ -2,8 +2,8 @@
 
 use super::*;
 use super::cmd::run_stdout;
-use super::{dep_info::write_dependency_file, output::{Message, Extra}, cmd::{run, wait_child, verify_status}};
-use crate::utils::{MsgQueue, MsgWriter, ColorString, Color, WriteColorExt};
+use super::{dep_info::write_dependency_file, output::{Message, Extra}, cmd::{run, wait_child, verify_status, response_file}};
+use crate::utils::{MsgQueue, MsgWriter, ColorString, Color, WriteColorExt, paths};
 use std::io::{Read, Write};
 use std::process::Command;
 use std::hash::Hasher;
@@ -35,6 +35,11 @@ impl BinType {
     pub fn is_static(self) -> bool { self == Self::Static }
     pub fn is_library(self) -> bool { self != Self::Exe }
 
+    // Get output file name prefix based on target architecture
+    pub fn prefix(self, target: &str) -> &'static str {
+        if self.is_library() && !target.contains("windows") { "lib" } else { "" }
+    }
+
     // Get output file extension based on target architecture
     pub fn ext(self, target: &str) -> &'static str {
         if target.contains("windows") {
@@ -75,6 +80,8 @@ pub struct Output {
     pub path: PathBuf,
     // paths of extra artifacts that were generated during compile
     pub extra: Vec<PathBuf>,
+    // symbolic links to the versioned output binary (link path, file name it points to)
+    pub symlinks: Vec<(PathBuf, PathBuf)>,
     // was the output binary linked in this build step
     pub did_link: bool,
 }
@@ -114,6 +121,8 @@ pub enum Artifact {
     Exp,
     // Apple - debug information file
     Dsym,
+    // ELF - separate debug information file
+    Debug,
 }
 
 impl Artifact {
@@ -124,11 +133,12 @@ impl Artifact {
             Self::Lib => "lib",
             Self::Exp => "exp",
             Self::Dsym => "dSYM",
+            Self::Debug => "debug",
         }
     }
 
     pub fn is_debug_info(self) -> bool {
-        matches!(self, Self::Pdb | Self::Dsym)
+        matches!(self, Self::Pdb | Self::Dsym | Self::Debug)
     }
 
     pub fn is_auxiliary(self) -> bool {
@@ -173,8 +183,21 @@ pub struct Build {
     files: Vec<PathBuf>,
     includes: Vec<PathBuf>,
     libraries: Vec<PathBuf>,
+    // libraries that depend on each other and are searched repeatedly by the linker
+    link_groups: Vec<Vec<PathBuf>>,
+    // static libraries whose objects are all linked, even if nothing references them
+    whole_archive: Vec<PathBuf>,
+    // version script or symbol list that restricts the symbols exported by a shared library
+    exports: Option<PathBuf>,
+    // version of a shared library, appended to the output file name on ELF targets
+    version: Option<String>,
+    // version embedded in the soname, defaults to the major version
+    soversion: Option<String>,
+    system_libraries: Vec<String>,
+    search_paths: Vec<PathBuf>,
     objects: Vec<PathBuf>,
     env: Vec<(OsString, OsString)>,
+    path_prefix_maps: Vec<(PathBuf, PathBuf)>,
     skip: Skip,
 
     src_dir: PathBuf,
@@ -210,8 +233,16 @@ impl Build {
             files: Vec::new(),
             includes: Vec::new(),
             libraries: Vec::new(),
+            link_groups: Vec::new(),
+            whole_archive: Vec::new(),
+            exports: None,
+            version: None,
+            soversion: None,
+            system_libraries: Vec::new(),
+            search_paths: Vec::new(),
             objects: Vec::new(),
             env: Vec::new(),
+            path_prefix_maps: Vec::new(),
             skip: Skip::default(),
             src_dir: PathBuf::new(),
             out_dir: PathBuf::new(),
@@ -306,6 +337,66 @@ impl Build {
         self
     }
     
+    /// Link libraries that depend on each other as a group - must also be added with `libraries`
+    pub fn link_group<P: AsRef<Path>, I: IntoIterator<Item=P>>(&mut self, paths: I) -> &mut Self {
+        self.link_groups.push(paths.into_iter().map(|p| p.as_ref().into()).collect());
+        self
+    }
+    
+    /// Link all objects of static libraries - must also be added with `libraries`
+    pub fn whole_archive<P: AsRef<Path>, I: IntoIterator<Item=P>>(&mut self, paths: I) -> &mut Self {
+        for p in paths {
+            self.whole_archive.push(p.as_ref().into());
+        }
+        self
+    }
+    
+    /// Only export the symbols in a version script or a symbol list (one per line) from a shared library
+    pub fn exports<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.exports = Some(path.as_ref().into());
+        self
+    }
+    
+    /// Set the version of a shared library (e.g. `1.2.3` -> `libfoo.so.1.2.3`)
+    pub fn version(&mut self, version: &str) -> &mut Self {
+        self.version = Some(version.to_string());
+        self
+    }
+    
+    /// Set the version in the soname of a shared library (e.g. `1` -> `libfoo.so.1`)
+    pub fn soversion(&mut self, soversion: &str) -> &mut Self {
+        self.soversion = Some(soversion.to_string());
+        self
+    }
+    
+    /// Add a system library to link in by name (e.g. `m` -> `-lm`/`m.lib`)
+    pub fn system_library(&mut self, name: &str) -> &mut Self {
+        self.system_libraries.push(name.to_string());
+        self
+    }
+    
+    /// Add multiple system libraries to link in by name
+    pub fn system_libraries<S: AsRef<str>, I: IntoIterator<Item=S>>(&mut self, names: I) -> &mut Self {
+        for n in names {
+            self.system_libraries.push(n.as_ref().to_string());
+        }
+        self
+    }
+
+    /// Add a directory to the `-L` or search path for system libraries - absolute path
+    pub fn search_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.search_paths.push(path.as_ref().into());
+        self
+    }
+    
+    /// Add multiple directories to the `-L` or search path for system libraries - absolute path
+    pub fn search_paths<P: AsRef<Path>, I: IntoIterator<Item=P>>(&mut self, paths: I) -> &mut Self {
+        for p in paths {
+            self.search_paths.push(p.as_ref().into());
+        }
+        self
+    }
+    
     /// Add an arbitrary object file to link in - absolute path
     pub fn object<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
         self.objects.push(path.as_ref().into());
@@ -320,6 +411,12 @@ impl Build {
         self
     }
 
+    /// Replace the path prefix `from` with `to` in outputs of reproducible builds
+    pub fn remap_path_prefix<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) -> &mut Self {
+        self.path_prefix_maps.push((from.as_ref().into(), to.as_ref().into()));
+        self
+    }
+
     /// Set path used to store output from compiler tools
     pub fn output_cache<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
         self.output_cache_path = Some(path.as_ref().into());
@@ -424,6 +521,7 @@ impl Build {
     pub fn compile(&mut self) -> Result<Output, Error> {
         self.ensure_cwd();
         let dst = self.output_path();
+        let (bin, symlinks) = self.output_links(&dst);
         let objs = self.object_paths()?;
 
         self.get_cache_compilers()?;
@@ -439,7 +537,7 @@ impl Build {
             .unwrap_or_else(|| dst.with_extension("stderr"));
 
         let did_link = if !self.skip.link {
-            self.assemble(&dst, &objs, stderr_cache)?;
+            self.assemble(&bin, &objs, stderr_cache)?;
             true
         } else {
             self.stderr.writer().load_cache_from_path(stderr_cache)?;
@@ -447,6 +545,10 @@ impl Build {
             false
         };
 
+        for (link, target) in symlinks.iter() {
+            paths::symlink(target, link).map_err(|e| Error::io(format!("{e:#}")))?;
+        }
+
         let extra = self.artifacts()
             .into_iter()
             .map(|a| dst.with_extension(a.ext()))
@@ -455,6 +557,7 @@ impl Build {
         Ok(Output { 
             extra,
             did_link,
+            symlinks,
             path: dst, 
             objs: compiled, 
         })
@@ -482,8 +585,7 @@ impl Build {
         bin_type: BinType,
         toolchain: &Toolchain,
     ) -> PathBuf {
-        // TODO: lib prefix for library names?
-        PathBuf::from(name)
+        PathBuf::from(format!("{}{name}", bin_type.prefix(toolchain.target())))
             .with_extension(bin_type.ext(toolchain.target()))        
     }
     
@@ -509,6 +611,10 @@ impl Build {
             if profile.debug {
                 a.push(Artifact::Dsym);
             }
+        } else if !toolchain.target().contains("windows") {
+            if profile.debug && profile.split_debuginfo == SplitDebuginfo::Packed && !bin_type.is_static() {
+                a.push(Artifact::Debug);
+            }
         }
         a
     }
@@ -557,18 +663,52 @@ impl Build {
         self.rel(&path).to_path_buf()
     }
 
+    // Versioned ELF shared libraries are linked to `libfoo.so.1.2.3`, the soname `libfoo.so.1`
+    // links to the binary and `libfoo.so` links to the soname so the linker can find it
+    fn output_links(&self, dst: &Path) -> (PathBuf, Vec<(PathBuf, PathBuf)>) {
+        let file = dst.file_name().unwrap().to_string_lossy();
+        match self.versioned_names(&file) {
+            Some((real, soname)) if real != soname => (dst.with_file_name(&real), vec![
+                (dst.with_file_name(&soname), real.into()),
+                (dst.to_path_buf(), soname.into()),
+            ]),
+            Some((real, _)) => (dst.with_file_name(&real), vec![(dst.to_path_buf(), real.into())]),
+            None => (dst.to_path_buf(), Vec::new()),
+        }
+    }
+
+    // File names of the binary and the soname of a versioned ELF shared library
+    fn versioned_names(&self, file: &str) -> Option<(String, String)> {
+        if !self.bin_type.is_shared() || !self.is_elf() {
+            return None;
+        }
+        let version = self.version.as_deref()?;
+        let soversion = self.soversion.as_deref()
+            .unwrap_or_else(|| version.split('.').next().unwrap());
+        Some((format!("{file}.{version}"), format!("{file}.{soversion}")))
+    }
+
+    fn linker(&self) -> Option<&Linker> {
+        self.options.linker.as_ref().or(self.profile.linker.as_ref())
+    }
+
     fn artifacts(&self) -> Vec<Artifact> {
         Self::output_artifacts(self.bin_type, self.lang, &self.toolchain, &self.profile)
     }
 
-    fn object_paths(&self) -> Result<Vec<Object>, Error> {
-        let mut objs = Vec::new();
+    fn obj_dir_path(&self) -> PathBuf {
         let obj_dir = if let Some(dir) = &self.obj_dir {
             dir.clone()
         } else {
             self.out_dir.join(format!("{}.dir", self.name))
         };
-        let obj_dir = self.rel(&obj_dir);
+        self.rel(&obj_dir).to_path_buf()
+    }
+
+    fn object_paths(&self) -> Result<Vec<Object>, Error> {
+        let mut objs = Vec::new();
+        let obj_dir = self.obj_dir_path();
+        let obj_dir = obj_dir.as_path();
         let src_dir = self.rel(&self.src_dir);
         // We want to run tests with files that do not exist so we check here
         let is_test = self.env.iter()
@@ -578,8 +718,9 @@ impl Build {
         for file in self.files.iter() {
             let hashed_name: String;
             // If the file has a parent, prefix the `filename` with
-            // a hash of the parent to ensure uniqueness
-            let name: &Path = if let Some(parent) = file.parent() {
+            // a hash of the parent to ensure uniqueness. The parent is relative to
+            // the source directory, so object names do not depend on where the sources are
+            let name: &Path = if let Some(parent) = file.strip_prefix(&self.src_dir).unwrap_or(file).parent() {
                 let parent = parent
                     .to_str()
                     .ok_or_else(|| Error::new(
@@ -885,22 +1026,28 @@ impl Build {
         }
 
         // Combine source objects and user-provided objects
-        let all_objs: Vec<_> = objs
+        let mut all_objs: Vec<_> = objs
             .iter()
             .map(|o| o.dst.clone())
             .chain(self.objects.clone())
             .collect();
 
-        // Add objects to the archive in limited-length batches. This helps keep
-        // the length of the command line within a reasonable length to avoid
-        // blowing system limits on limiting platforms like Windows.
-        for (i, chunk) in all_objs.chunks(100).enumerate() {
-            // Since we need to link multiple chunks sequentially, we can exit early in the loop
-            if self.bin_type.is_static() {
-                self.assemble_static(dst, chunk, i == 0, stdout.clone(), stderr.clone())
-            } else {
-                self.assemble_shared(dst, chunk, stdout.clone(), stderr.clone())
-            }?;
+        // The order of objects is reflected in the output binary
+        if self.profile.reproducible {
+            all_objs.sort();
+        }
+
+        // Long command lines are written to response files in `Build::run_step`.
+        // Archivers without them get the objects appended in limited-length batches
+        // to avoid blowing system limits on the length of the command line.
+        if self.bin_type.is_static() {
+            let ar = &self.toolchain.tools_for(self.lang).unwrap().ar;
+            let batch = if self.response_files(ar.kind()) { all_objs.len() } else { 100 };
+            for (i, objs) in archive_batches(&all_objs, batch).enumerate() {
+                self.assemble_static(dst, objs, i == 0, stdout.clone(), stderr.clone())?;
+            }
+        } else {
+            self.assemble_shared(dst, &all_objs, stdout, stderr)?;
         }
 
         // Non-msvc targets (those using `ar`) need a separate step to add
@@ -909,18 +1056,61 @@ impl Build {
         if self.bin_type.is_static() && !self.target().contains("msvc") {
             let ar = &self.toolchain.tools_for(self.lang).unwrap().ar;
             let mut cmd = ar.to_command();            
-            run(cmd.arg("s").arg(dst), ar.name())?;
+            run_stdout(cmd.arg(if self.deterministic_ar() { "sD" } else { "s" }).arg(dst), ar.name())?;
+        }
+
+        // Move debug information of ELF binaries into a separate file
+        if self.artifacts().contains(&Artifact::Debug) {
+            self.split_debug_info(dst, &self.output_path().with_extension(Artifact::Debug.ext()))?;
         }
 
         Ok(())
     }
 
+    fn split_debug_info(&self, dst: &Path, debug: &Path) -> Result<(), Error> {
+        let objcopy = self.toolchain.binutil("objcopy")
+            .ok_or_else(|| Error::tool_not_found(
+                "Failed to find tool `objcopy` required for `split-debuginfo = \"packed\"`"
+            ))?;
+        
+        // copy debug sections into the `.debug` file
+        let mut cmd = Command::new(&objcopy);
+        cmd.arg("--only-keep-debug").arg(dst).arg(debug);
+        run_stdout(&mut cmd, "objcopy")?;
+
+        // remove debug sections from the binary and point debuggers to the `.debug` file
+        let mut cmd = Command::new(&objcopy);
+        cmd.arg(if self.profile.strip == Strip::Symbols { "--strip-all" } else { "--strip-debug" });
+        let mut link = OsString::from("--add-gnu-debuglink=");
+        link.push(debug);
+        cmd.arg(link).arg(dst);
+        run_stdout(&mut cmd, "objcopy")?;
+        Ok(())
+    }
+
     fn assemble_shared(&self, dst: &Path, objs: &[PathBuf], stdout: Writer, stderr: Writer) -> Result<(), Error> {
         let mut tool = self.toolchain.tools_for(self.lang).unwrap().ld.clone();
     
         let msvc = tool.family().is_msvc();
 
         self.add_default_link_flags(&mut tool);
+
+        // select the linker used by the compiler driver
+        if let Some(linker) = self.linker() {
+            if !msvc {
+                let flags = linker.flags(tool.family()).ok_or_else(|| Error::new(
+                    ErrorKind::InvalidArgument,
+                    &format!(
+                        "Target `{}` uses linker `{linker}`, which `{}` can only select if it is named `ld.<name>`",
+                        self.name,
+                        tool.name()
+                    )
+                ))?;
+                for flag in flags {
+                    tool.arg(flag);
+                }
+            }
+        }
         
         for flag in self.options.ld_flags.iter() {
             tool.arg(flag);
@@ -936,6 +1126,10 @@ impl Build {
 
         self.add_libraries(&mut cmd, tool.family());
 
+        if self.bin_type.is_shared() {
+            self.add_exports(&mut cmd, tool.family())?;
+        }
+
         if msvc {
             cmd.args(objs);
         }
@@ -973,7 +1167,8 @@ impl Build {
             cmd.args(&self.options.ar_flags);
             // c - recreate if exists
             // q - quick create - append to end
-            cmd.arg("cq");
+            // D - zero timestamps, uids and gids
+            cmd.arg(if self.deterministic_ar() { "cqD" } else { "cq" });
             cmd.arg(dst);
         }
 
@@ -1000,16 +1195,6 @@ impl Build {
         if !self.profile.debug {
             cmd.arg("-DNDEBUG");
         }
-        // TODO: VERSION defines for target
-        // TODO: Move STATIC/EXPORTS defines to a place where 
-        // it can be propagated between target dependencies
-        if self.bin_type.is_library() {
-            if self.bin_type.is_static() {
-                cmd.arg(format!("-D{}_STATIC", self.name.to_uppercase()));
-            } else {
-                cmd.arg(format!("-D{}_EXPORTS", self.name.to_uppercase()));
-            }
-        }
     }
 
     fn add_includes(&self, cmd: &mut Command) {
@@ -1020,12 +1205,62 @@ impl Build {
 
     fn add_libraries(&self, cmd: &mut Command, family: ToolFamily) {
         let target = self.target();
-        for v in self.libraries.iter() {
-            // msvc and windows-clang only accepts `.lib` files for linking
-            if family.is_msvc() || (family.is_clang() && target.contains("windows")) {
-                cmd.arg(v.with_extension(Artifact::Lib.ext()));
-            } else {
-                cmd.arg(v);
+        let link = !self.bin_type.is_static();
+        if link {
+            for v in self.search_paths.iter() {
+                if family.is_msvc() {
+                    let mut arg = OsString::from("-LIBPATH:");
+                    arg.push(v);
+                    cmd.arg(arg);
+                } else {
+                    let mut arg = OsString::from("-L");
+                    arg.push(v);
+                    cmd.arg(arg);
+                }
+            }
+        }
+        // archives only contain their own objects, except with msvc which merges `.lib` files
+        if link || family.is_msvc() {
+            // msvc and the apple linker already search libraries repeatedly
+            let groups = link && !family.is_msvc() && !target.contains("apple");
+            for v in self.libraries.iter() {
+                if groups && self.link_groups.iter().any(|g| g.first() == Some(v)) {
+                    cmd.arg("-Wl,--start-group");
+                }
+                // msvc and windows-clang only accepts `.lib` files for linking
+                let path = if family.is_msvc() || (family.is_clang() && target.contains("windows")) {
+                    v.with_extension(Artifact::Lib.ext())
+                } else {
+                    v.clone()
+                };
+                if link && self.whole_archive.contains(v) {
+                    if family.is_msvc() {
+                        let mut arg = OsString::from("-WHOLEARCHIVE:");
+                        arg.push(&path);
+                        cmd.arg(arg);
+                    } else if target.contains("apple") {
+                        let mut arg = OsString::from("-Wl,-force_load,");
+                        arg.push(&path);
+                        cmd.arg(arg);
+                    } else {
+                        cmd.arg("-Wl,--whole-archive").arg(&path).arg("-Wl,--no-whole-archive");
+                    }
+                } else {
+                    cmd.arg(&path);
+                }
+                if groups && self.link_groups.iter().any(|g| g.last() == Some(v)) {
+                    cmd.arg("-Wl,--end-group");
+                }
+            }
+        }
+        // system libraries go after the libraries that need them
+        if link {
+            for v in self.system_libraries.iter() {
+                if family.is_msvc() {
+                    cmd.arg(format!("{v}.{}", Artifact::Lib.ext()));
+                } else {
+                    cmd.arg(format!("-l{v}"));
+                }
             }
         }
         if !self.bin_type.is_static() && family.is_msvc() {
@@ -1059,7 +1294,7 @@ impl Build {
                 Artifact::Pdb => "-PDB:",
                 Artifact::Ilk => "-ILK:",
                 Artifact::Lib => "-IMPLIB:",
-                Artifact::Exp | Artifact::Dsym => continue,
+                Artifact::Exp | Artifact::Dsym | Artifact::Debug => continue,
             });
             arg.push(dst.with_extension(a.ext()));
             cmd.arg(arg);
@@ -1179,6 +1414,10 @@ impl Build {
             } else {
                 tool.arg("-g");
             }
+            // leave debug information in `.dwo` files next to the objects
+            if self.profile.split_debuginfo == SplitDebuginfo::Unpacked && self.is_elf() {
+                tool.arg("-gsplit-dwarf");
+            }
         }
     }
 
@@ -1303,6 +1542,15 @@ impl Build {
                 tool.arg("-fno-exceptions"); 
             }
             
+            if self.profile.reproducible {
+                // strip absolute paths from debug info and `__FILE__` expansions
+                for (from, to) in self.path_prefix_maps() {
+                    let map = format!("{}={}", from.display(), to.display());
+                    tool.arg(format!("-ffile-prefix-map={map}"));
+                    tool.arg(format!("-fdebug-prefix-map={map}"));
+                }
+            }
+
             if tool.family() == ToolFamily::Clang && target.contains("android") {
                 // For compatibility with code that doesn't use pre-defined `__ANDROID__` macro.
                 // If compiler used via ndk-build or cmake (officially supported build methods)
@@ -1354,10 +1602,60 @@ impl Build {
         }
     }
 
+    fn add_exports(&self, cmd: &mut Command, family: ToolFamily) -> Result<(), Error> {
+        let path = match &self.exports {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::io(format!("Failed to read exports `{}`: {e}", path.display())))?;
+        let is_version_script = is_version_script(&contents);
+        let target = self.target();
+        let dir = self.obj_dir_path();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut arg = OsString::new();
+        if target.contains("windows") {
+            // module-definition file, gnu linkers for windows accept it as an input
+            let def = dir.join(format!("{}.def", self.name));
+            let mut s = String::from("EXPORTS\n");
+            for symbol in export_symbols(&contents) {
+                s.push_str(&format!("    {symbol}\n"));
+            }
+            std::fs::write(&def, s)?;
+            if family.is_msvc() {
+                arg.push("-DEF:");
+            }
+            arg.push(def);
+        } else if target.contains("apple") {
+            // c symbols have a leading underscore in mach-o
+            let list = dir.join(format!("{}.exports", self.name));
+            let mut s = String::new();
+            for symbol in export_symbols(&contents) {
+                s.push_str(&format!("_{symbol}\n"));
+            }
+            std::fs::write(&list, s)?;
+            arg.push("-Wl,-exported_symbols_list,");
+            arg.push(list);
+        } else if is_version_script {
+            arg.push("-Wl,--version-script,");
+            arg.push(path);
+        } else {
+            let script = dir.join(format!("{}.map", self.name));
+            let mut s = String::from("{\n  global:\n");
+            for symbol in export_symbols(&contents) {
+                s.push_str(&format!("    {symbol};\n"));
+            }
+            s.push_str("  local: *;\n};\n");
+            std::fs::write(&script, s)?;
+            arg.push("-Wl,--version-script,");
+            arg.push(script);
+        }
+        cmd.arg(arg);
+        Ok(())
+    }
+
     fn add_default_link_flags(&self, tool: &mut Tool) {
-        // TODO: -rdynamic | -Wl,--export-dynamic | .exp files (MSVC) (i.e. exporting symbols from exe -> dll)
-        // The only legitimate use case for this behaviour is so that debugging tools can
-        // extract prettier stack-traces from the executable being debugged.
         let target = self.target();
         if tool.family().is_msvc() {
             // suppress microsoft logo
@@ -1386,14 +1684,25 @@ impl Build {
             if self.options.warnings.errors { 
                 tool.arg("-WX"); 
             }
+            // no timestamps in the output
+            if self.profile.reproducible {
+                tool.arg("-Brepro");
+            }
             // TODO: MSVC entry point -ENTRY
             // TODO: MSVC manifest setup
         } else {
-            // TODO: Set the soname flag? -Wl,soname,$OUTPUT
-            
             // shared library flag
             if self.bin_type.is_shared() { 
                 tool.arg("-shared");
+                // name that executables record to find the library at runtime
+                let file = Self::output_name(&self.name, self.bin_type, &self.toolchain);
+                let file = file.to_string_lossy();
+                if self.is_elf() {
+                    let soname = self.versioned_names(&file).map_or_else(|| file.to_string(), |(_, s)| s);
+                    tool.arg(format!("-Wl,-soname,{soname}"));
+                } else if target.contains("apple") {
+                    tool.arg(format!("-Wl,-install_name,@rpath/{file}"));
+                }
             }
             // link to static c/unix libraries
             else if self.static_crt() {
@@ -1406,6 +1715,16 @@ impl Build {
             // unix-only flags
             if !target.contains("windows") {
                 
+                // debug information is stripped after it is split into a separate file
+                if !self.artifacts().contains(&Artifact::Debug) {
+                    let apple = target.contains("apple");
+                    match self.profile.strip {
+                        Strip::None => {}
+                        Strip::Debuginfo => { tool.arg(if apple { "-Wl,-S" } else { "-Wl,--strip-debug" }); }
+                        Strip::Symbols => { tool.arg(if apple { "-Wl,-x" } else { "-Wl,--strip-all" }); }
+                    }
+                }
+
                 if self.profile.is_lto_enabled() {
                     self.add_lto_flags(tool);
                 }
@@ -1420,9 +1739,35 @@ impl Build {
                     tool.arg(format!("-Wl,-rpath,{}", self.profile.rpath));
                 }
             }
+            // export symbols from the executable to libraries loaded at runtime,
+            // msvc executables only export `__declspec(dllexport)` symbols
+            if self.options.export_dynamic && !self.bin_type.is_library() {
+                tool.arg(if target.contains("windows") { "-Wl,--export-all-symbols" } else { "-rdynamic" });
+            }
         }
     }
 
+    // Path prefixes that are replaced in reproducible builds, the current directory is mapped first
+    // so that more specific user-provided prefixes take precedence
+    fn path_prefix_maps(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.cwd.as_deref()
+            .map(|cwd| (cwd, Path::new(".")))
+            .into_iter()
+            .chain(self.path_prefix_maps.iter().map(|(a, b)| (a.as_path(), b.as_path())))
+    }
+
+    // Whether the target produces ELF binaries
+    fn is_elf(&self) -> bool {
+        let target = self.target();
+        !target.contains("apple") && !target.contains("windows") && !target.contains("wasm")
+    }
+
+    // Whether the archiver should be run in deterministic mode
+    fn deterministic_ar(&self) -> bool {
+        // Apple's archiver does not support `D`, `ZERO_AR_DATE` is used instead
+        self.profile.reproducible && !self.target().contains("apple")
+    }
+
     fn get_dwarf_version(&self) -> Option<u32> {
         // Tentatively matches the DWARF version defaults as of rustc 1.62.
         let target = self.target();
@@ -1552,6 +1897,12 @@ impl Build {
         arg
     }
 
+    // Whether long command lines of the tool are written to response files
+    fn response_files(&self, kind: ToolKind) -> bool {
+        // Apple's `ar` does not support them
+        !(kind == ToolKind::Archiver && self.target().contains("apple"))
+    }
+
     fn run_step(
         &self, 
         cmd: &mut Command, 
@@ -1564,6 +1915,25 @@ impl Build {
         for &(ref a, ref b) in self.env.iter() {
             cmd.env(a, b);
         }
+        // Fix `__DATE__`/`__TIME__` expansions and other embedded timestamps
+        if self.profile.reproducible {
+            cmd.env("SOURCE_DATE_EPOCH", source_date_epoch());
+        }
+        let mut rsp_cmd = if !self.response_files(kind) {
+            None
+        } else {
+            // `ar` requires the operation and archive to be passed on the command line
+            let keep = if kind == ToolKind::Archiver && !family.is_msvc() {
+                let dst = self.output_path();
+                cmd.get_args().position(|a| Path::new(a) == dst).map_or(0, |i| i + 1)
+            } else {
+                0
+            };
+            let args: Vec<_> = cmd.get_args().collect();
+            let path = self.obj_dir_path().join(format!("{:016x}.rsp", crate::utils::hash_u64(&args)));
+            response_file(cmd, &path, family.is_msvc(), keep)?
+        };
+        let cmd = rsp_cmd.as_mut().unwrap_or(cmd);
         let mut child = run(cmd, name)?;
         let includes = if let OutputMode::Original = self.output_mode {
             Self::forward_output(child.stderr.take().unwrap(), stderr);
@@ -1657,6 +2027,82 @@ impl AsmFileExt {
 }
 
 
+// Timestamp used for reproducible builds, `SOURCE_DATE_EPOCH` if set or the unix epoch
+fn source_date_epoch() -> OsString {
+    std::env::var_os("SOURCE_DATE_EPOCH")
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| OsString::from("0"))
+}
+
+
+// Exports are a version script rather than a symbol list if they contain a node outside of comments
+fn is_version_script(contents: &str) -> bool {
+    strip_export_comments(contents).contains('{')
+}
+
+// Symbols exported by a version script (`global` sections) or a symbol list (one per line)
+fn export_symbols(contents: &str) -> Vec<String> {
+    let text = strip_export_comments(contents);
+    if !text.contains('{') {
+        return text.split_whitespace().map(|v| v.to_string()).collect();
+    }
+    // nodes can nest blocks like `extern "C++" { ... };`, so braces are matched by depth
+    let mut symbols = Vec::new();
+    let mut depth = 0;
+    let mut global = true;
+    let mut item = String::new();
+    for c in text.chars() {
+        match c {
+            '{' => {
+                depth += 1;
+                if depth == 1 {
+                    global = true;
+                }
+                item.clear();
+            }
+            '}' => {
+                depth -= 1;
+                item.clear();
+            }
+            ';' if depth > 0 => {
+                let mut v = item.trim();
+                if let Some(rest) = v.strip_prefix("global:") {
+                    global = true;
+                    v = rest.trim();
+                } else if let Some(rest) = v.strip_prefix("local:") {
+                    global = false;
+                    v = rest.trim();
+                }
+                let v = v.trim_matches('"');
+                if global && !v.is_empty() {
+                    symbols.push(v.to_string());
+                }
+                item.clear();
+            }
+            // names of nodes and the nodes they depend on
+            ';' => item.clear(),
+            c => item.push(c),
+        }
+    }
+    symbols
+}
+
+// Remove `/* */` and `#` comments of a symbol list or version script
+fn strip_export_comments(contents: &str) -> String {
+    let mut text = String::new();
+    let mut rest = contents;
+    while let Some(start) = rest.find("/*") {
+        text.push_str(&rest[..start]);
+        rest = rest[start..].find("*/").map_or("", |end| &rest[start + end + 2..]);
+    }
+    text.push_str(rest);
+    text.lines()
+        .map(|v| v.split('#').next().unwrap())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+
 fn parallel<T, F, E, R>(
     items: &[T],
     not_parallel: bool,
@@ -1707,3 +2153,117 @@ where
 
     Ok(outputs)
 }
+
+
+// Split objects into batches of at most `batch` objects, there is always at least one batch
+fn archive_batches(objs: &[PathBuf], batch: usize) -> impl Iterator<Item = &[PathBuf]> {
+    let batch = batch.max(1);
+    (0..objs.len().max(1)).step_by(batch).map(move |i| &objs[i..objs.len().min(i + batch)])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_symbols_from_list_and_version_script() {
+        assert_eq!(export_symbols("foo\n# comment\n  bar  \n\n"), ["foo", "bar"]);
+        let script = "/* api */\nFOO_1.0 {\n  global:\n    foo;\n    bar; # c\n  local: *;\n};\nFOO_2.0 {\n  baz;\n} FOO_1.0;\n";
+        assert_eq!(export_symbols(script), ["foo", "bar", "baz"]);
+        let script = "FOO {\n  global:\n    extern \"C++\" {\n      ns::*;\n      \"foo(int)\";\n    };\n    bar;\n  local: *;\n};\n";
+        assert_eq!(export_symbols(script), ["ns::*", "foo(int)", "bar"]);
+        // braces in comments do not make a symbol list a version script
+        let list = "/* exported {api} */\nfoo\nbar # {internal}\n";
+        assert!(!is_version_script(list));
+        assert_eq!(export_symbols(list), ["foo", "bar"]);
+        assert!(is_version_script("# symbols\n{ global: foo; local: *; };"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn gnu_exports() {
+        let dir = std::env::temp_dir().join("ccargo-test-gnu-exports");
+        std::fs::create_dir_all(&dir).unwrap();
+        let exports = dir.join("foo.map");
+        std::fs::write(&exports, "{ global: foo; local: *; };").unwrap();
+        let mut build = Build::new("foo", BinType::Shared, Toolchain::default().unwrap());
+        build.out_dir(&dir).exports(&exports).ensure_cwd();
+
+        let mut cmd = Command::new("cc");
+        build.add_exports(&mut cmd, ToolFamily::Gnu).unwrap();
+        let mut arg = OsString::from("-Wl,--version-script,");
+        arg.push(&exports);
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), [&arg]);
+        drop(std::fs::remove_dir_all(&dir));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn gnu_export_dynamic() {
+        let mut build = Build::new("foo", BinType::Exe, Toolchain::default().unwrap());
+        build.options.export_dynamic = true;
+        let mut tool = build.toolchain.tools_for(Language::C).unwrap().ld.clone();
+        build.add_default_link_flags(&mut tool);
+        assert!(tool.args().iter().any(|v| v == "-rdynamic"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn gnu_soname() {
+        let mut build = Build::new("foo", BinType::Shared, Toolchain::default().unwrap());
+        build.version("1.2.3");
+        let mut tool = build.toolchain.tools_for(Language::C).unwrap().ld.clone();
+        build.add_default_link_flags(&mut tool);
+        assert!(tool.args().iter().any(|v| v == "-Wl,-soname,libfoo.so.1"));
+
+        let (real, links) = build.output_links(Path::new("out/libfoo.so"));
+        assert_eq!(real, Path::new("out/libfoo.so.1.2.3"));
+        assert_eq!(links, [
+            (PathBuf::from("out/libfoo.so.1"), PathBuf::from("libfoo.so.1.2.3")),
+            (PathBuf::from("out/libfoo.so"), PathBuf::from("libfoo.so.1")),
+        ]);
+    }
+
+    #[test]
+    fn archive_batches_of_objects() {
+        let objs: Vec<_> = (0..250).map(|i| PathBuf::from(format!("{i}.o"))).collect();
+        let batches: Vec<_> = archive_batches(&objs, 100).collect();
+        assert_eq!(batches.iter().map(|v| v.len()).collect::<Vec<_>>(), [100, 100, 50]);
+        assert_eq!(batches[2][0], Path::new("200.o"));
+        assert_eq!(archive_batches(&objs, objs.len()).count(), 1);
+        assert_eq!(archive_batches(&[], 100).map(|v| v.len()).collect::<Vec<_>>(), [0]);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn archive_many_objects() {
+        let dir = std::env::temp_dir().join("ccargo-test-archive");
+        drop(std::fs::remove_dir_all(&dir));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files: Vec<_> = (0..150)
+            .map(|i| {
+                let path = dir.join(format!("f{i}.c"));
+                std::fs::write(&path, format!("int f{i}(void) {{ return {i}; }}\n")).unwrap();
+                path
+            })
+            .collect();
+        let mut build = Build::new("many", BinType::Static, Toolchain::default().unwrap());
+        build.out_dir(dir.join("out")).files(&files).ensure_cwd();
+        build.compile().unwrap();
+
+        let output = Command::new("ar").arg("t").arg(dir.join("out").join("libmany.a")).output().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 150);
+        drop(std::fs::remove_dir_all(&dir));
+    }
+
+    #[test]
+    fn linker_flags() {
+        let flags = |linker: &str, family| Linker::from(linker.to_string()).flags(family);
+        assert_eq!(flags("mold", ToolFamily::Gnu), Some(vec!["-fuse-ld=mold".into()]));
+        assert_eq!(flags("/opt/ld.mold", ToolFamily::Gnu), Some(vec!["-B/opt".into(), "-fuse-ld=mold".into()]));
+        assert_eq!(flags("/opt/mylinker", ToolFamily::Clang), Some(vec!["--ld-path=/opt/mylinker".into()]));
+        // gcc would silently link with its default linker
+        assert_eq!(flags("/opt/mylinker", ToolFamily::Gnu), None);
+    }
+}
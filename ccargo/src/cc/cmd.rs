@@ -1,8 +1,17 @@
 use super::Error;
+use std::ffi::OsString;
 use std::io::Read;
+use std::path::Path;
 use std::process::{Command, Child, Stdio, ExitStatus};
 
 
+// Maximum length of a command line before its arguments are moved into a response file
+#[cfg(windows)]
+const MAX_COMMAND_LEN: usize = 30_000;
+#[cfg(not(windows))]
+const MAX_COMMAND_LEN: usize = 128 * 1024;
+
+
 // Run command and return command's stdout output
 pub(super) fn run_stdout(cmd: &mut Command, program: &str) -> Result<Vec<u8>, Error> {
     let mut child = run(cmd, program)?;
@@ -72,6 +81,102 @@ pub(super) fn wait_child(cmd: &Command, program: &str, child: &mut Child) -> Res
     }
 }
 
+// Create a command that reads its arguments from the response file at `path` 
+// if the command line of `cmd` would exceed the platform length limit, 
+// the first `keep` arguments are still passed on the command line
+pub(super) fn response_file(cmd: &Command, path: &Path, msvc: bool, keep: usize) -> Result<Option<Command>, Error> {
+    if command_len(cmd) <= MAX_COMMAND_LEN {
+        return Ok(None);
+    }
+
+    let mut contents = String::new();
+    for arg in cmd.get_args().skip(keep) {
+        let arg = arg.to_str().ok_or_else(|| 
+            Error::invalid_arg(format!("Argument `{}` is not valid unicode", arg.to_string_lossy())))?;
+        if msvc {
+            quote_msvc(arg, &mut contents);
+        } else {
+            quote_gnu(arg, &mut contents);
+        }
+        contents.push('\n');
+    }
+
+    // MSVC tools expect UTF-16 response files
+    let bytes = if msvc {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(contents.encode_utf16().flat_map(|c| c.to_le_bytes()));
+        bytes
+    } else {
+        contents.into_bytes()
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| Error::io(e.to_string()))?;
+    }
+    std::fs::write(path, bytes).map_err(|e| Error::io(e.to_string()))?;
+
+    let mut rsp = Command::new(cmd.get_program());
+    if let Some(dir) = cmd.get_current_dir() {
+        rsp.current_dir(dir);
+    }
+    for (k, v) in cmd.get_envs() {
+        match v {
+            Some(v) => rsp.env(k, v),
+            None => rsp.env_remove(k),
+        };
+    }
+    rsp.args(cmd.get_args().take(keep));
+    let mut arg = OsString::from("@");
+    arg.push(path);
+    rsp.arg(arg);
+    Ok(Some(rsp))
+}
+
+
+// Approximate length of the command line used to invoke `cmd`
+fn command_len(cmd: &Command) -> usize {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| a.len() + 3)
+        .sum()
+}
+
+
+// Quote argument for a GNU-style response file (backslash escapes any character)
+fn quote_gnu(arg: &str, out: &mut String) {
+    for c in arg.chars() {
+        if matches!(c, ' ' | '\t' | '\n' | '\r' | '\'' | '"' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+
+// Quote argument for a MSVC response file (same rules as the windows command line)
+fn quote_msvc(arg: &str, out: &mut String) {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        out.push_str(arg);
+        return;
+    }
+    out.push('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+        } else {
+            // backslashes are only escaped when they precede a quote
+            if c == '"' {
+                for _ in 0..=backslashes { out.push('\\'); }
+            }
+            backslashes = 0;
+        }
+        out.push(c);
+    }
+    for _ in 0..backslashes { out.push('\\'); }
+    out.push('"');
+}
+
+
 fn read_output<R: Read>(input: &mut Option<R>) -> Vec<u8> {
     let mut output = Vec::new();
     input
@@ -81,3 +186,38 @@ fn read_output<R: Read>(input: &mut Option<R>) -> Vec<u8> {
         .unwrap();
     output
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gnu(arg: &str) -> String {
+        let mut s = String::new();
+        quote_gnu(arg, &mut s);
+        s
+    }
+
+    fn msvc(arg: &str) -> String {
+        let mut s = String::new();
+        quote_msvc(arg, &mut s);
+        s
+    }
+
+    #[test]
+    fn quote_gnu_args() {
+        assert_eq!(gnu("-O2"), "-O2");
+        assert_eq!(gnu("a b"), "a\\ b");
+        assert_eq!(gnu("C:\\dir\\a.o"), "C:\\\\dir\\\\a.o");
+        assert_eq!(gnu("-DX=\"y\""), "-DX=\\\"y\\\"");
+    }
+
+    #[test]
+    fn quote_msvc_args() {
+        assert_eq!(msvc("-O2"), "-O2");
+        assert_eq!(msvc(""), "\"\"");
+        assert_eq!(msvc("C:\\my dir\\a.obj"), "\"C:\\my dir\\a.obj\"");
+        assert_eq!(msvc("C:\\my dir\\"), "\"C:\\my dir\\\\\"");
+        assert_eq!(msvc("-DX=\"y\""), "\"-DX=\\\"y\\\"\"");
+    }
+}
@@ -0,0 +1,259 @@
+use super::{supported_flags, Tool};
+use super::probe_cache::{ProbeCache, ProbeResults};
+use crate::core::Config;
+use crate::utils::{IResult, hash_u64, BinaryWriter, BinaryReader};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+
+/// Feature probe whose result is written to a generated `config.h`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Check {
+    // header can be included (e.g. `sys/epoll.h` -> `HAVE_SYS_EPOLL_H`)
+    HasHeader(String),
+    // function can be linked (e.g. `strlcpy` -> `HAVE_STRLCPY`)
+    HasFunction(String),
+    // size of a type in bytes (e.g. `long` -> `SIZEOF_LONG`)
+    Sizeof(String),
+    // source file compiles
+    Compiles(PathBuf),
+    // flag is supported by the compiler (e.g. `-mavx2` -> `HAVE_FLAG_MAVX2`)
+    HasFlag(String),
+}
+
+impl Check {
+    /// Autoconf-style name of the define that holds the result of the check
+    pub fn default_define(&self) -> String {
+        let (prefix, name) = match self {
+            Self::HasHeader(v) => ("HAVE_", v.as_str()),
+            Self::HasFunction(v) => ("HAVE_", v.as_str()),
+            Self::Sizeof(v) => ("SIZEOF_", v.as_str()),
+            Self::Compiles(v) => ("HAVE_", v.file_stem().and_then(|v| v.to_str()).unwrap_or_default()),
+            Self::HasFlag(v) => ("HAVE_FLAG_", v.as_str()),
+        };
+        let name = name
+            .trim_start_matches(|c: char| !c.is_ascii_alphanumeric())
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+            .to_uppercase();
+        format!("{prefix}{name}")
+    }
+}
+
+
+/// Arguments used when compiling the probes of a target
+pub struct Probe<'a> {
+    // compiler of the target
+    pub tool: &'a Tool,
+    // whether the target is compiled as C++
+    pub cpp: bool,
+    // include, define and compiler flags of the target
+    pub args: Vec<OsString>,
+    // system libraries linked by the target
+    pub links: Vec<String>,
+    // hash of the toolchain, results are cached per toolchain
+    pub toolchain: u64,
+}
+
+
+/// Load the checks cache from disk
+pub fn load(config: &Config) -> IResult<()> {
+    CACHE.load(config)
+}
+
+
+/// Save the checks cache to disk
+pub fn save(config: &Config) -> IResult<()> {
+    CACHE.save(config)
+}
+
+
+/// Run a check with the toolchain and flags of a target, returns the value of its define
+/// (`None` if the check failed)
+pub fn run(probe: &Probe, check: &Check, config: &Config) -> IResult<Option<u64>> {
+    // flags are checked and cached by `supported_flags`
+    if let Check::HasFlag(flag) = check {
+        let supported = supported_flags::is_flag_supported(probe.tool, flag, probe.cpp, config)?;
+        return Ok(supported.then_some(1));
+    }
+
+    let contents = match check {
+        Check::Compiles(path) => Some(std::fs::read(path)?),
+        _ => None,
+    };
+    let key = hash_u64(&(check, &contents, probe.cpp, &probe.args, &probe.links));
+    if let Some(result) = CACHE.lock(config)?.get(&probe.toolchain).and_then(|v| v.get(&key)) {
+        return Ok(*result);
+    }
+
+    // Compiling probes might take long, so we don't hold the lock
+    let result = match check {
+        Check::HasHeader(header) => {
+            let src = format!("#include <{header}>\nint main(void) {{ return 0; }}\n");
+            compiles(probe, &src, false, config)?.then_some(1)
+        }
+        Check::HasFunction(function) => {
+            // declared without its header like autoconf, so only the linker can find it
+            let src = if probe.cpp {
+                format!("extern \"C\" char {function}(void);\nint main(void) {{ return {function}(); }}\n")
+            } else {
+                format!("char {function}(void);\nint main(void) {{ return {function}(); }}\n")
+            };
+            compiles(probe, &src, true, config)?.then_some(1)
+        }
+        Check::Sizeof(ty) => sizeof(probe, ty, config)?,
+        Check::Compiles(_) => {
+            let src = String::from_utf8_lossy(contents.as_deref().unwrap()).into_owned();
+            compiles(probe, &src, false, config)?.then_some(1)
+        }
+        Check::HasFlag(_) => unreachable!(),
+    };
+
+    CACHE.lock(config)?.entry(probe.toolchain).or_default().insert(key, result);
+    Ok(result)
+}
+
+
+// Size of a type found by compiling `sizeof(T) <= N` assertions, which works when cross-compiling
+fn sizeof(probe: &Probe, ty: &str, config: &Config) -> IResult<Option<u64>> {
+    let fits = |n: u64| {
+        let src = format!(
+            "#include <stddef.h>\n#include <stdint.h>\nstatic char check[(sizeof({ty}) <= {n}) ? 1 : -1];\nint main(void) {{ return check[0]; }}\n"
+        );
+        compiles(probe, &src, false, config)
+    };
+    let (mut lo, mut hi) = (1, 4096);
+    if !fits(hi)? {
+        return Ok(None);
+    }
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if fits(mid)? { hi = mid } else { lo = mid + 1 }
+    }
+    Ok(Some(lo))
+}
+
+
+// Compile (and optionally link) a probe source file in its own directory
+fn compiles(probe: &Probe, src: &str, link: bool, config: &Config) -> IResult<bool> {
+    let dir = CACHE.probe_dir(config)?;
+    let name = if probe.cpp { "check.cpp" } else { "check.c" };
+    std::fs::write(dir.join(name), src)?;
+
+    let msvc = probe.tool.family().is_msvc();
+    let mut cmd = probe.tool.to_command();
+    cmd.args(&probe.args);
+    if link {
+        cmd.arg(if msvc { format!("-Fe{name}.out") } else { format!("-o{name}.out") });
+    } else {
+        cmd.arg("-c");
+        cmd.arg(if msvc { format!("-Fo{name}.o") } else { format!("-o{name}.o") });
+    }
+    cmd.arg(name);
+    if link {
+        for lib in probe.links.iter() {
+            cmd.arg(if msvc { format!("{lib}.lib") } else { format!("-l{lib}") });
+        }
+    }
+    let output = cmd
+        .current_dir(&dir)
+        .output();
+    drop(std::fs::remove_dir_all(&dir));
+    Ok(output?.status.success())
+}
+
+
+// Runtime cache of check results per toolchain hash
+type Cache = HashMap<u64, HashMap<u64, Option<u64>>>;
+
+lazy_static::lazy_static! {
+    static ref CACHE: ProbeCache<Cache> = ProbeCache::new("checks");
+}
+
+
+/// Contents of the `<name>_config.h` of a target with the results of `checks` (define name, result)
+pub fn config_header<'a, I: IntoIterator<Item = (&'a str, Option<u64>)>>(name: &str, checks: I) -> String {
+    let guard = name.to_uppercase();
+    let mut s = format!(
+        "/* Generated by ccargo from the `config` checks of the target */\n#ifndef {guard}_CONFIG_H\n#define {guard}_CONFIG_H\n\n"
+    );
+    for (define, result) in checks {
+        match result {
+            Some(v) => s.push_str(&format!("#define {define} {v}\n")),
+            None => s.push_str(&format!("/* #undef {define} */\n")),
+        }
+    }
+    s.push_str("\n#endif\n");
+    s
+}
+
+
+impl ProbeResults for Cache {
+    fn is_empty(&self) -> bool {
+        HashMap::is_empty(self)
+    }
+
+    fn serialize(&self, w: &mut BinaryWriter) {
+        w.write_u64(self.len() as u64);
+        for (toolchain, results) in self.iter() {
+            w.write_u64(*toolchain);
+            w.write_u64(results.len() as u64);
+            for (key, result) in results.iter() {
+                w.write_u64(*key);
+                w.write_u8(result.is_some() as u8);
+                w.write_u64(result.unwrap_or(0));
+            }
+        }
+    }
+
+    fn deserialize(r: &mut BinaryReader) -> Option<Self> {
+        let n = r.read_u64()?;
+        let mut c = Cache::with_capacity(n as usize);
+        for _ in 0..n {
+            let toolchain = r.read_u64()?;
+            let m = r.read_u64()?;
+            let mut results = HashMap::with_capacity(m as usize);
+            for _ in 0..m {
+                let key = r.read_u64()?;
+                let some = r.read_u8()? != 0;
+                let value = r.read_u64()?;
+                results.insert(key, some.then_some(value));
+            }
+            c.insert(toolchain, results);
+        }
+        Some(c)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_defines_and_header() {
+        assert_eq!(Check::HasHeader("sys/epoll.h".into()).default_define(), "HAVE_SYS_EPOLL_H");
+        assert_eq!(Check::HasFunction("strlcpy".into()).default_define(), "HAVE_STRLCPY");
+        assert_eq!(Check::Sizeof("unsigned long".into()).default_define(), "SIZEOF_UNSIGNED_LONG");
+        assert_eq!(Check::Compiles("checks/atomics.c".into()).default_define(), "HAVE_ATOMICS");
+        assert_eq!(Check::HasFlag("-mavx2".into()).default_define(), "HAVE_FLAG_MAVX2");
+        assert_eq!(
+            config_header("my_lib", [("HAVE_A", Some(1)), ("HAVE_B", None), ("SIZEOF_LONG", Some(8))]),
+            "/* Generated by ccargo from the `config` checks of the target */\n\
+             #ifndef MY_LIB_CONFIG_H\n#define MY_LIB_CONFIG_H\n\n\
+             #define HAVE_A 1\n/* #undef HAVE_B */\n#define SIZEOF_LONG 8\n\n#endif\n"
+        );
+    }
+
+    #[test]
+    fn flags_checked_twice() {
+        let config = Config::default().unwrap();
+        let toolchain = crate::cc::Toolchain::default().unwrap();
+        let tool = &toolchain.tools_for(crate::cc::Language::C).unwrap().cc;
+        let probe = Probe{tool, cpp: false, args: Vec::new(), links: Vec::new(), toolchain: 0};
+        for _ in 0..2 {
+            assert_eq!(run(&probe, &Check::HasFlag("-fccargo-unsupported-check".into()), &config).unwrap(), None);
+            assert_eq!(run(&probe, &Check::HasFlag("-Wall".into()), &config).unwrap(), Some(1));
+        }
+    }
+}
@@ -14,9 +14,13 @@ pub mod output;
 pub use output::Message;
 
 mod platform;
-pub use platform::{RustcTarget, host_platform, host_triple, validate_target};
+pub use platform::{RustcTarget, host_platform, host_triple, target_platform, validate_target};
 
 mod toolchain;
 pub use toolchain::{ToolKind, ToolFamily, Tool, Toolchain, which};
 
+mod probe_cache;
+
 pub mod supported_flags;
+
+pub mod config_checks;
@@ -1,7 +1,9 @@
+use super::ToolFamily;
 use crate::utils::InternedString;
 use std::collections::BTreeSet;
+use std::ffi::OsString;
 use std::fmt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use serde::{Serialize, Deserialize};
 
 
@@ -31,6 +33,10 @@ pub struct Options {
     pub asm_flags: FlagSet,
     // flags for unix-like targets
     pub unix: UnixFlags,
+    // linker used instead of the compiler's default (overrides the profile's linker)
+    pub linker: Option<Linker>,
+    // export all symbols from an executable so loaded libraries can call back into it
+    pub export_dynamic: bool,
 }
 
 
@@ -138,6 +144,114 @@ pub enum Lto {
 }
 
 
+// Linker used by GCC/Clang compiler drivers when linking binaries
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum Linker {
+    // LLVM linker
+    Lld,
+    // Modern linker
+    Mold,
+    // GNU gold linker
+    Gold,
+    // Path to a custom linker executable
+    Path(PathBuf),
+}
+
+impl Linker {
+    // Name of the linker executable that the compiler driver looks for
+    pub fn program(&self) -> &Path {
+        match self {
+            Self::Lld => Path::new("ld.lld"),
+            Self::Mold => Path::new("ld.mold"),
+            Self::Gold => Path::new("ld.gold"),
+            Self::Path(p) => p,
+        }
+    }
+
+    // Flags passed to the compiler driver to select this linker, 
+    // `None` if the compiler driver cannot select it
+    pub fn flags(&self, family: ToolFamily) -> Option<Vec<OsString>> {
+        let name = match self {
+            Self::Lld => "lld",
+            Self::Mold => "mold",
+            Self::Gold => "gold",
+            // clang accepts a path directly
+            Self::Path(p) if family.is_clang() => {
+                let mut arg = OsString::from("--ld-path=");
+                arg.push(p);
+                return Some(vec![arg]);
+            }
+            // gcc only selects `ld.<name>` with `-fuse-ld=<name>`, searching `-B` directories first
+            Self::Path(p) => {
+                let file_name = p.file_name().and_then(|x| x.to_str()).unwrap_or_default();
+                let name = file_name.strip_prefix("ld.")?;
+                let mut dir = OsString::from("-B");
+                dir.push(p.parent().unwrap_or(Path::new(".")));
+                return Some(vec![dir, format!("-fuse-ld={name}").into()]);
+            }
+        };
+        Some(vec![format!("-fuse-ld={name}").into()])
+    }
+}
+
+impl fmt::Display for Linker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        String::from(self.clone()).fmt(f)
+    }
+}
+
+impl From<String> for Linker {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "lld" => Self::Lld,
+            "mold" => Self::Mold,
+            "gold" => Self::Gold,
+            _ => Self::Path(PathBuf::from(value)),
+        }
+    }
+}
+
+impl From<Linker> for String {
+    fn from(value: Linker) -> Self {
+        match value {
+            Linker::Lld => "lld".to_string(),
+            Linker::Mold => "mold".to_string(),
+            Linker::Gold => "gold".to_string(),
+            Linker::Path(p) => p.display().to_string(),
+        }
+    }
+}
+
+
+// Information that is stripped from linked binaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strip {
+    #[default]
+    // keep everything
+    None,
+    // remove debug information
+    Debuginfo,
+    // remove debug information and symbol tables
+    Symbols,
+}
+
+
+// Controls where debug information of linked binaries is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SplitDebuginfo {
+    #[default]
+    // debug information is kept inside the binary
+    Off,
+    // debug information is moved to a single file next to the binary (e.g. `.debug`)
+    Packed,
+    // debug information is left in the object files (e.g. `.dwo`)
+    Unpacked,
+}
+
+
 // Represents a generic optimization level used to select different compiler optimizations
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
 pub enum OptLevel {
@@ -210,6 +324,14 @@ pub struct Profile {
     pub dir_name: InternedString,    
     // rpath used for dynamic linking on platforms that support it (default is $ORIGIN)
     pub rpath: InternedString,
+    // produce outputs that do not depend on the build location or time
+    pub reproducible: bool,
+    // information removed from linked binaries
+    pub strip: Strip,
+    // how debug information is stored for linked binaries
+    pub split_debuginfo: SplitDebuginfo,
+    // linker used instead of the compiler's default
+    pub linker: Option<Linker>,
 }
 
 impl Profile {
@@ -234,6 +356,10 @@ impl Profile {
             lto: Lto::Off,
             dir_name: InternedString::from("debug"),
             rpath: InternedString::from("$ORIGIN"),
+            reproducible: false,
+            strip: Strip::None,
+            split_debuginfo: SplitDebuginfo::Off,
+            linker: None,
         }
     }
 
@@ -246,6 +372,10 @@ impl Profile {
             lto: Lto::Fat,
             dir_name: InternedString::from("release"),
             rpath: InternedString::from("$ORIGIN"),
+            reproducible: false,
+            strip: Strip::None,
+            split_debuginfo: SplitDebuginfo::Off,
+            linker: None,
         }
     }
 }
@@ -2,6 +2,7 @@ use crate::utils::{IResult, lev_distance};
 use std::collections::HashSet;
 
 pub use ccargo_platform::RustcTarget;
+use ccargo_platform::Cfg;
 
 // PRIMARY TARGETS
 //  aarch64-unknown-linux-gnu      ARM64 Linux (kernel 4.1, glibc 2.17+)
@@ -25,6 +26,57 @@ pub fn host_platform() -> &'static RustcTarget {
 }
 
 
+// Get the platform of `target`, the host platform is used if `target` is the host
+pub fn target_platform(target: &str) -> RustcTarget {
+    let host = host_platform();
+    if host.target() == target {
+        return RustcTarget::new(target.to_string(), host.cfgs().to_vec());
+    }
+    // cfgs of other targets are derived from the triple, as `rustc` may not know the target
+    let parts = target.split('-').collect::<Vec<_>>();
+    let arch = match parts[0] {
+        "i386" | "i586" | "i686" => "x86",
+        v if v.starts_with("armv") || v.starts_with("thumb") => "arm",
+        v => v,
+    };
+    let os = if target.contains("windows") {
+        "windows"
+    } else if target.contains("apple-darwin") {
+        "macos"
+    } else if target.contains("android") {
+        "android"
+    } else if target.contains("linux") {
+        "linux"
+    } else if target.contains("freebsd") {
+        "freebsd"
+    } else {
+        "none"
+    };
+    let env = ["msvc", "gnu", "musl"]
+        .into_iter()
+        .find(|v| parts.last().is_some_and(|p| p.starts_with(v)))
+        .unwrap_or("");
+    let mut cfgs = vec![
+        Cfg::KeyPair("target_arch".into(), arch.into()),
+        Cfg::KeyPair("target_os".into(), os.into()),
+        Cfg::KeyPair("target_env".into(), env.into()),
+    ];
+    if parts.len() > 2 {
+        cfgs.push(Cfg::KeyPair("target_vendor".into(), parts[1].into()));
+    }
+    let family = match os {
+        "windows" => Some("windows"),
+        "none" => None,
+        _ => Some("unix"),
+    };
+    if let Some(family) = family {
+        cfgs.push(Cfg::Name(family.into()));
+        cfgs.push(Cfg::KeyPair("target_family".into(), family.into()));
+    }
+    RustcTarget::new(target.to_string(), cfgs)
+}
+
+
 // Get the host target triple
 pub fn host_triple() -> &'static str {
     if cfg!(windows) {
@@ -0,0 +1,102 @@
+use crate::core::Config;
+use crate::utils::{IResult, BinaryWriter, BinaryReader};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+
+/// Results of probing a toolchain that can be stored in a `ProbeCache`
+pub trait ProbeResults: Default {
+    fn is_empty(&self) -> bool;
+    fn serialize(&self, w: &mut BinaryWriter);
+    fn deserialize(r: &mut BinaryReader) -> Option<Self>;
+}
+
+
+/// Probe results that are cached in memory and persisted in the ccargo home directory
+pub struct ProbeCache<T> {
+    // name of the cache directory (`<name>_cache`) and file (`<name>.bin`)
+    name: &'static str,
+    loaded: Mutex<bool>,
+    results: Mutex<T>,
+}
+
+impl<T: ProbeResults> ProbeCache<T> {
+    pub fn new(name: &'static str) -> Self {
+        Self{name, loaded: Mutex::new(false), results: Mutex::default()}
+    }
+
+    /// Load the cache from disk
+    pub fn load(&self, config: &Config) -> IResult<()> {
+        let mut loaded = self.loaded.lock().unwrap();
+        if *loaded {
+            return Ok(());
+        }
+        let path = self.file(config);
+        if !path.exists() {
+            *loaded = true;
+            return Ok(());
+        }
+        let data = std::fs::read(path)?;
+        // Results that cannot be read (e.g. written by another version) are probed again
+        *self.results.lock().unwrap() = T::deserialize(&mut BinaryReader(&data)).unwrap_or_default();
+        *loaded = true;
+        Ok(())
+    }
+
+    /// Save the cache to disk
+    pub fn save(&self, config: &Config) -> IResult<()> {
+        // Nothing to save if nothing was probed
+        let results = self.results.lock().unwrap();
+        if results.is_empty() {
+            return Ok(());
+        }
+
+        let mut w = BinaryWriter::with_capacity(8);
+        results.serialize(&mut w);
+        std::fs::create_dir_all(self.root(config))?;
+        std::fs::write(self.file(config), w.into_inner())?;
+
+        // Delete the build directory if it exists to remove unnecessary junk
+        drop(std::fs::remove_dir_all(self.build_dir(config)));
+        Ok(())
+    }
+
+    /// Delete the cache directory
+    pub fn remove(&self, config: &Config) -> IResult<()> {
+        drop(std::fs::remove_dir_all(self.root(config)));
+        Ok(())
+    }
+
+    /// Results in memory, loading them from disk first if they were not loaded yet
+    pub fn lock(&self, config: &Config) -> IResult<MutexGuard<'_, T>> {
+        self.load(config)?;
+        Ok(self.results.lock().unwrap())
+    }
+
+    /// Create an empty directory that a single probe is built in,
+    /// so probes that run at the same time never share files
+    pub fn probe_dir(&self, config: &Config) -> IResult<PathBuf> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        let dir = self.build_dir(config).join(format!("{}-{id}", std::process::id()));
+        drop(std::fs::remove_dir_all(&dir));
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Directory of the build files of probes
+    pub fn build_dir(&self, config: &Config) -> PathBuf {
+        self.root(config).join("build")
+    }
+
+    // Path of the cache root directory
+    fn root(&self, config: &Config) -> PathBuf {
+        config.home().join(format!("{}_cache", self.name))
+    }
+
+    // Path of the binary cache file
+    fn file(&self, config: &Config) -> PathBuf {
+        self.root(config).join(format!("{}.bin", self.name))
+    }
+}
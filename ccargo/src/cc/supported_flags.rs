@@ -1,46 +1,26 @@
 use super::Tool;
+use super::probe_cache::{ProbeCache, ProbeResults};
 use crate::core::Config;
 use crate::utils::{IResult, hash_u64, BinaryWriter, BinaryReader};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::Mutex;
 
 
 /// Load the flags cache from disk
 pub fn load(config: &Config) -> IResult<()> {
-    let mut loaded = LOADED.lock().unwrap();
-    if *loaded {
-        return Ok(());
-    }
-    let path = flags_cache_file(config)?;
-    if !path.exists() {
-        *loaded = true;
-        return Ok(());
-    }
-    let data = std::fs::read(path)?;
-    *CACHE.lock().unwrap() = deserialize_cache(&data)
-        .ok_or_else(|| anyhow::anyhow!("Failed to deserialize cache"))?;
-    *loaded = true;
-    Ok(())
+    CACHE.load(config)
 }
 
 
 /// Save the flags cache to disk
 pub fn save(config: &Config) -> IResult<()> {
-    // Save cache to disk
-    let out = serialize_cache(&CACHE.lock().unwrap())?;
-    std::fs::write(flags_cache_file(config)?, out)?;
-
-    // Delete the build directory if it exists to remove unnecessary junk
-    drop(std::fs::remove_dir_all(flags_cache_build_dir(config)?));
-    Ok(())
+    CACHE.save(config)
 }
 
 
 /// Delete the flags cache directory
 pub fn remove(config: &Config) -> IResult<()> {
-    drop(std::fs::remove_dir_all(flags_cache_root(config)?));
-    Ok(())
+    CACHE.remove(config)
 }
 
 
@@ -51,15 +31,36 @@ pub fn is_flag_supported(
     cpp: bool,
     config: &Config,
 ) -> IResult<bool> {
-    // Ensure the cache has been loaded from disk if it exists
-    load(config)?;
+    is_supported(tool, flag, flag, cpp, false, config)
+}
+
+
+/// Check if a flag is supported by the given compiler when linking an executable
+pub fn is_link_flag_supported(
+    tool: &Tool,
+    flag: &str,
+    cpp: bool,
+    config: &Config,
+) -> IResult<bool> {
+    is_supported(tool, flag, &format!("link:{flag}"), cpp, true, config)
+}
+
 
-    // Check if the flag is in the cache, or insert new flags for the tool
+// Check if a flag is supported, using `key` to cache the result
+fn is_supported(
+    tool: &Tool,
+    flag: &str,
+    key: &str,
+    cpp: bool,
+    link: bool,
+    config: &Config,
+) -> IResult<bool> {
+    // Check if the flag was already probed, or insert new flags for the tool
     {
-        let mut cache = CACHE.lock().unwrap();
+        let mut cache = CACHE.lock(config)?;
         if let Some(flags) = cache.get(tool.path()) {
-            if flags.contains(flag) {
-                return Ok(true);
+            if let Some(supported) = flags.get(key) {
+                return Ok(supported);
             }
         } else {
             cache.insert(tool.path().to_path_buf(), SupportedFlags::default());
@@ -68,15 +69,14 @@ pub fn is_flag_supported(
 
     // Check if the flag is supported by the tool 
     // This might take long, so we don't hold the lock
-    let supported = check_is_flag_supported(tool, flag, cpp, config)?;
+    let supported = check_is_flag_supported(tool, flag, cpp, link, config)?;
     
-    // Insert the flag into the cache
+    // Insert the result into the cache, unsupported flags are not probed again either
     CACHE
-        .lock()
-        .unwrap()
+        .lock(config)?
         .get_mut(tool.path())
         .unwrap()
-        .insert(flag);
+        .insert(key, supported);
 
     Ok(supported)
 }
@@ -86,26 +86,7 @@ pub fn is_flag_supported(
 type Cache = HashMap<PathBuf, SupportedFlags>;
 
 lazy_static::lazy_static! {
-    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::new());
-    static ref LOADED: Mutex<bool> = Mutex::new(false);
-}
-
-
-// Path of the flags cache root directory
-fn flags_cache_root(config: &Config) -> IResult<PathBuf> {
-    Ok(config.home().join("flags_cache"))
-}
-
-
-// Path of the binary flags cache file
-fn flags_cache_file(config: &Config) -> IResult<PathBuf> {
-    Ok(flags_cache_root(config)?.join("flags.bin"))
-}
-
-
-// Path of the build directory used for checking flags
-fn flags_cache_build_dir(config: &Config) -> IResult<PathBuf> {
-    Ok(flags_cache_root(config)?.join("build"))
+    static ref CACHE: ProbeCache<Cache> = ProbeCache::new("flags");
 }
 
 
@@ -114,18 +95,22 @@ fn check_is_flag_supported(
     tool: &Tool, 
     flag: &str,
     cpp: bool,
+    link: bool,
     config: &Config,
 ) -> IResult<bool> {
     let (path, name) = ensure_flag_check_file(flag, cpp, config)?;
-    let supported = tool.to_command()
-        .arg(flag)
-        .arg("-c")
+    let mut cmd = tool.to_command();
+    cmd.arg(flag);
+    if link {
+        cmd.arg("-o").arg(format!("{name}.out"));
+    } else {
+        cmd.arg("-c");
+    }
+    let output = cmd
         .arg(name)
         .current_dir(path.parent().unwrap())
-        .output()?
-        .stderr
-        .is_empty();
-    Ok(supported)
+        .output()?;
+    Ok(output.status.success() && output.stderr.is_empty())
 }
 
 
@@ -134,7 +119,7 @@ fn ensure_flag_check_file(flag: &str, cpp: bool, config: &Config) -> IResult<(Pa
     let hash = hash_u64(&flag);
     let ext = if cpp { "cpp" } else { "c" };
     let fname = format!("{hash:016x}_flags_check.{ext}");
-    let out_dir = flags_cache_build_dir(config)?;
+    let out_dir = CACHE.build_dir(config);
     if !out_dir.exists() {
         std::fs::create_dir_all(&out_dir)?;
     }
@@ -146,55 +131,76 @@ fn ensure_flag_check_file(flag: &str, cpp: bool, config: &Config) -> IResult<(Pa
 }
 
 
-// Helper struct for efficiently storing set of supported flags
+// Helper struct for efficiently storing whether probed flags are supported
 #[derive(Default)]
-struct SupportedFlags(HashSet<u64>);
+struct SupportedFlags(HashMap<u64, bool>);
 impl SupportedFlags {
-    fn contains(&self, flag: &str) -> bool {
-        self.0.contains(&hash_u64(&flag))
+    fn get(&self, flag: &str) -> Option<bool> {
+        self.0.get(&hash_u64(&flag)).copied()
     }
-    fn insert(&mut self, flag: &str) {
-        self.0.insert(hash_u64(&flag));
+    fn insert(&mut self, flag: &str, supported: bool) {
+        self.0.insert(hash_u64(&flag), supported);
     }
     
     fn serialize(&self, w: &mut BinaryWriter) {
-        w.reserve((self.0.len() + 1) * 8);
+        w.reserve((self.0.len() * 2 + 1) * 8);
         w.write_u64(self.0.len() as u64);
-        for v in self.0.iter() {
-            w.write_u64(*v);
+        for (k, v) in self.0.iter() {
+            w.write_u64(*k);
+            w.write_u64(*v as u64);
         }
     }
 
     fn deserialize(r: &mut BinaryReader) -> Option<Self> {
         let n = r.read_u64()?;
-        let mut h = HashSet::with_capacity(n as usize);
+        let mut h = HashMap::with_capacity(n as usize);
         for _ in 0..n {
-            h.insert(r.read_u64()?);
+            h.insert(r.read_u64()?, r.read_u64()? != 0);
         }
         Some(Self(h))
     }
 }
 
-// Serialize cache to Vec<u8>
-fn serialize_cache(cache: &Cache) -> IResult<Vec<u8>> {
-    let mut w = BinaryWriter::with_capacity(8);
-    w.write_u64(cache.len() as u64);
-    for (k, v) in cache.iter() {
-        w.write_path(k);
-        v.serialize(&mut w);
+impl ProbeResults for Cache {
+    fn is_empty(&self) -> bool {
+        HashMap::is_empty(self)
+    }
+
+    fn serialize(&self, w: &mut BinaryWriter) {
+        w.write_u64(self.len() as u64);
+        for (k, v) in self.iter() {
+            w.write_path(k);
+            v.serialize(w);
+        }
+    }
+
+    fn deserialize(r: &mut BinaryReader) -> Option<Self> {
+        let n = r.read_u64()?;
+        let mut c = Cache::with_capacity(n as usize);
+        for _ in 0..n {
+            let path = r.read_path()?;
+            let flags = SupportedFlags::deserialize(r)?;
+            c.insert(path, flags);
+        }
+        Some(c)
     }
-    Ok(w.into_inner())
 }
 
-// Deserialize cache from bytes
-fn deserialize_cache(bytes: &[u8]) -> Option<Cache> {
-    let mut r = BinaryReader(bytes);
-    let n = r.read_u64()?;
-    let mut c = Cache::with_capacity(n as usize);
-    for _ in 0..n {
-        let path = r.read_path()?;
-        let flags = SupportedFlags::deserialize(&mut r)?;
-        c.insert(path, flags);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cc::{Language, Toolchain};
+
+    #[test]
+    fn unsupported_flags_stay_unsupported() {
+        let config = Config::default().unwrap();
+        let toolchain = Toolchain::default().unwrap();
+        let cc = &toolchain.tools_for(Language::C).unwrap().cc;
+        for _ in 0..2 {
+            assert!(!is_flag_supported(cc, "-fccargo-unsupported-flag", false, &config).unwrap());
+            assert!(!is_link_flag_supported(cc, "-fuse-ld=ccargo-missing", false, &config).unwrap());
+            assert!(is_flag_supported(cc, "-Wall", false, &config).unwrap());
+        }
     }
-    Some(c)
 }
@@ -135,6 +135,18 @@ impl Toolchain {
         }
     }
 
+    // Find an auxiliary binary utility (e.g. `objcopy`) that works with this toolchain's target
+    pub fn binutil(&self, name: &str) -> Option<PathBuf> {
+        if host_triple() != self.target() {
+            if let Some(prefix) = prefix_for_target(self.target(), Language::C) {
+                if let Some(path) = which(format!("{prefix}-{name}")) {
+                    return Some(path);
+                }
+            }
+        }
+        which(name).or_else(|| which(format!("llvm-{name}")))
+    }
+
     fn new_priv(target: &str, cc_path: Option<PathBuf>, cxx_path: Option<PathBuf>) -> Result<Self, Error> {
         // Validate target
         if let Err(e) = validate_target(target) {
@@ -1011,7 +1023,14 @@ pub fn which(exe: impl AsRef<Path>) -> Option<PathBuf> {
     let path_entries = std::env::var_os("PATH")?;
     std::env::split_paths(&path_entries).find_map(|path_entry| {
         let mut path = path_entry.join(exe);
-        path.set_extension(std::env::consts::EXE_EXTENSION);
+        // Append the extension rather than replacing it, so that names like `ld.lld` are preserved
+        let ext = std::env::consts::EXE_EXTENSION;
+        if !ext.is_empty() && path.extension() != Some(OsStr::new(ext)) {
+            let mut p = path.into_os_string();
+            p.push(".");
+            p.push(ext);
+            path = p.into();
+        }
         if path.exists() {
             return Some(path)
         }
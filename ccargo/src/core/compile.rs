@@ -1,7 +1,7 @@
 use crate::core::*;
-use crate::cc::{Build, Profile, Toolchain, Language, Artifact, Output as CCOutput};
-use crate::utils::{Graph, MsgQueue, IResult, CommandExt, lev_distance};
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::cc::{Build, Profile, Toolchain, Artifact, FlagSet, Language, Linker, Output as CCOutput, supported_flags, config_checks, which};
+use crate::utils::{Graph, MsgQueue, IResult, CommandExt, lev_distance, paths};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
@@ -18,6 +18,8 @@ pub struct Context<'a> {
     pub target_io: TargetIOMap,
     pub unit_graph: Graph<Unit>,
     pub fingerprints: Mutex<HashMap<Unit, Arc<Fingerprint>>>,
+    // path of the linker selected for each target that uses one
+    pub linkers: HashMap<Target, PathBuf>,
 }
 
 /// Inputs/Outputs for a given `Target`
@@ -38,6 +40,17 @@ pub struct TargetDeps {
     pub libs: Vec<PathBuf>,
     pub includes: BTreeSet<PathBuf>,
     pub defines: BTreeMap<String, Option<String>>,
+    pub links: Vec<String>,
+    pub search_paths: Vec<PathBuf>,
+    // libraries in `libs` that depend on each other and are linked as a group
+    pub link_groups: Vec<Vec<PathBuf>>,
+    // static libraries in `libs` whose objects are all linked
+    pub whole_archive: Vec<PathBuf>,
+    // compiler/linker flags required by interface libraries
+    pub cc_flags: FlagSet,
+    pub ld_flags: FlagSet,
+    // `.pc` files of the system libraries the target uses
+    pub pkg_config_files: Vec<PathBuf>,
 }
 
 
@@ -50,7 +63,7 @@ impl<'a> Context<'a> {
         packages: &PackageMap,
         main_package: &Package,
         selected: &[String],
-    ) -> Self {
+    ) -> IResult<Self> {
         let target_triple = toolchain.target();
 
         let mut cx = Self{
@@ -63,12 +76,13 @@ impl<'a> Context<'a> {
             unit_graph: Graph::new(),
             target_io: HashMap::new(),
             fingerprints: Mutex::default(),
+            linkers: HashMap::new(),
         };
 
         // Convert selected names into targets
         let selected = selected_targets(config, main_package, selected);
         if selected.is_empty() {
-            return cx;
+            return Ok(cx);
         }
 
         // Build unit map
@@ -77,6 +91,27 @@ impl<'a> Context<'a> {
         // Build unit graph
         cx.unit_graph = cx.units.build_graph(&selected);
 
+        // Static libraries in a `link-group` may depend on each other, any other cycle is an error
+        let cycles = cx.unit_graph.cycles();
+        let mut link_groups = Vec::new();
+        for cycle in cycles.iter(&cx.unit_graph) {
+            let cycle = cycle.cloned().collect::<Vec<_>>();
+            let grouped = cycle.iter().all(|unit| {
+                matches!(unit, Unit::Target(t) if t.kind == TargetKind::Static && t.link_group)
+            });
+            if !grouped {
+                let names = cycle.iter().map(|v| format!("`{}`", v.full_name())).collect::<Vec<_>>();
+                anyhow::bail!(
+                    "Dependency cycle between {}, static libraries that depend on each other must set `link-group = true`",
+                    names.join(", ")
+                );
+            }
+            link_groups.push(cycle);
+        }
+        let link_ranks = link_ranks(&cx.unit_graph, &link_groups);
+        // Members of a link group are built independently, so the graph can be made acyclic
+        cycles.remove_from_graph(&mut cx.unit_graph);
+
         // Collect dependency information for targets
         for unit in cx.unit_graph.nodes() {
             let target = if let Unit::Target(target) = unit {
@@ -84,15 +119,14 @@ impl<'a> Context<'a> {
             } else {
                 continue
             };
-            cx.target_deps.insert(target.clone(), TargetDeps::new(&cx, target));
+            cx.target_deps.insert(target.clone(), TargetDeps::new(&cx, target, &link_ranks));
         }
 
         // Calculate target inputs/outputs
         for unit in cx.unit_graph.nodes() {
-            let target = if let Unit::Target(target) = unit {
-                target
-            } else {
-                continue
+            let target = match unit {
+                Unit::Target(target) if target.kind.has_output() => target,
+                _ => continue,
             };
             let io = TargetIO::from_target(
                 target,
@@ -103,8 +137,18 @@ impl<'a> Context<'a> {
             );
             cx.target_io.insert(target.clone(), io);
         }
+
+        // Resolve selected linkers, each linker is only probed once per compiler driver
+        let mut resolved = HashMap::new();
+        for unit in cx.unit_graph.nodes() {
+            if let Unit::Target(target) = unit {
+                if let Some(path) = cx.resolve_linker(target, &mut resolved)? {
+                    cx.linkers.insert(target.clone(), path);
+                }
+            }
+        }
         
-        cx
+        Ok(cx)
     }
     
     // TODO: Check all units
@@ -129,12 +173,16 @@ impl<'a> Context<'a> {
             }
         }
         
+        // Persist flags that were checked while building
+        supported_flags::save(self.config)?;
+        config_checks::save(self.config)?;
+
         // Copy outputs to target directory
         outputs.copy_to(&self.layout.target())
     }
     
     pub fn run(&self, target: &Target, is_main: bool) -> IResult<()> {
-        if let TargetKind::Static | TargetKind::Shared = target.kind {
+        if let TargetKind::Static | TargetKind::Shared | TargetKind::System | TargetKind::Interface = target.kind {
             anyhow::bail!("Cannot run library target `{}`", target.full_name())
         }
 
@@ -148,6 +196,107 @@ impl<'a> Context<'a> {
 
         Ok(())
     }
+
+    /// Path of the linker selected for `target`
+    pub fn linker(&self, target: &Target) -> Option<&Path> {
+        self.linkers.get(target).map(|v| v.as_path())
+    }
+
+    // Path of the linker selected for `target`, verifying that it can be used by the toolchain
+    fn resolve_linker(
+        &self, 
+        target: &Target, 
+        resolved: &mut HashMap<(Linker, Language), PathBuf>,
+    ) -> IResult<Option<PathBuf>> {
+        let linker = match target.options.linker.as_ref().or(self.profile.linker.as_ref()) {
+            Some(v) if target.kind.has_output() && target.kind != TargetKind::Static => v,
+            _ => return Ok(None),
+        };
+        let lang = target.language();
+        if let Some(path) = resolved.get(&(linker.clone(), lang)) {
+            return Ok(Some(path.clone()));
+        }
+        let ld = match self.toolchain.tools_for(lang) {
+            Some(v) => &v.ld,
+            None => anyhow::bail!(
+                "Target `{}` uses linker `{linker}`, but the toolchain has no {lang:?} compiler", 
+                target.full_name()
+            ),
+        };
+        if ld.family().is_msvc() {
+            anyhow::bail!(
+                "Target `{}` uses linker `{linker}`, but selecting a linker is not supported for MSVC", 
+                target.full_name()
+            )
+        }
+        let path = match which(linker.program()) {
+            Some(v) => v,
+            None => anyhow::bail!(
+                "Target `{}` uses linker `{linker}`, but `{}` is not installed", 
+                target.full_name(), 
+                linker.program().display()
+            ),
+        };
+        let flags = match linker.flags(ld.family()) {
+            Some(v) => v,
+            None => anyhow::bail!(
+                "Target `{}` uses linker `{linker}`, but `{}` can only select a linker named `ld.<name>`", 
+                target.full_name(), 
+                ld.name()
+            ),
+        };
+        for flag in flags {
+            let flag = flag.to_string_lossy();
+            if !supported_flags::is_link_flag_supported(ld, &flag, lang.is_cxx(), self.config)? {
+                anyhow::bail!(
+                    "Target `{}` uses linker `{linker}`, but `{}` does not support `{flag}`", 
+                    target.full_name(), 
+                    ld.name()
+                )
+            }
+        }
+        resolved.insert((linker.clone(), lang), path.clone());
+        Ok(Some(path))
+    }
+
+    /// Compare the outputs and objects of two builds of the same targets byte for byte,
+    /// returns the files that differ (relative to the target directory) for each target
+    pub fn compare_outputs(&self, other: &Context) -> IResult<Vec<(TargetName, Vec<PathBuf>)>> {
+        // the builds may have loaded their packages separately, so targets are matched by name
+        let others = other.target_io.iter()
+            .map(|(target, io)| (target.full_name(), (target, io)))
+            .collect::<HashMap<_, _>>();
+        let mut differ = Vec::new();
+        for (target, io) in self.target_io.iter() {
+            let Some((other_target, other_io)) = others.get(&target.full_name()) else {
+                continue;
+            };
+            let root = self.layout.target();
+            let mut files = vec![(io.output.clone(), other_io.output.clone())];
+            let (obj_dir, other_obj_dir) = (target.obj_dir(self.layout), other_target.obj_dir(other.layout));
+            if obj_dir.exists() {
+                for obj in paths::walk_files(&obj_dir)? {
+                    // the directory also has the dependency files and compiler output of the objects
+                    if !obj.extension().is_some_and(|v| v == "o" || v == "obj") {
+                        continue;
+                    }
+                    let other_obj = other_obj_dir.join(obj.strip_prefix(&obj_dir)?);
+                    files.push((obj, other_obj));
+                }
+            }
+            let mut changed = Vec::new();
+            for (file, other_file) in files {
+                if std::fs::read(&other_file).ok() != Some(paths::read_bytes(&file)?) {
+                    changed.push(file.strip_prefix(&root).unwrap_or(&file).to_path_buf());
+                }
+            }
+            if !changed.is_empty() {
+                differ.push((target.full_name(), changed));
+            }
+        }
+        differ.sort();
+        Ok(differ)
+    }
 }
 
 
@@ -158,6 +307,11 @@ fn compile_unit(
     stdout: &MsgQueue<std::io::Stdout>,
     stderr: &MsgQueue<std::io::Stderr>,
 ) -> IResult<()> {
+    // Generated headers are written before checking dep-info, so the sources including them are rebuilt
+    if let Unit::Target(target) = unit {
+        target.gen_headers(cx)?;
+    }
+
     let fingerprint_path = unit.fingerprint_path(cx.layout);
     let (fingerprint, state) = fingerprint::prepare(
         cx,
@@ -166,12 +320,35 @@ fn compile_unit(
     )?;
 
     if state.is_fresh() {
+        // fresh steps show the same output as when they ran
+        if let Unit::Step(step) = unit {
+            step.replay(cx.layout, stdout.writer(), stderr.writer())?;
+        }
         return Ok(());
     }
 
     use crate::utils::{ColorString, WriteColorExt, Color};
     let stdout = stdout.writer();
     match unit {
+        // System libraries are already built and interface libraries have nothing to build
+        Unit::Target(target) if !target.kind.has_output() => {}
+        Unit::Target(target) if target.prebuilt.is_some() => {
+            outputs.add_prebuilt(cx, target);
+        }
+        Unit::Target(target) if target.external.is_some() => {
+            drop(stdout.push({
+                let mut msg = ColorString::new();
+                drop(msg.write_status_justified(
+                    &"Building", 
+                    Some(&target.full_name()), 
+                    Color::Green,
+                ));
+                msg
+            }.as_bytes()));
+
+            external::build(cx, target, stderr.writer())?;
+            outputs.add_prebuilt(cx, target);
+        }
         Unit::Target(target) => {            
             drop(stdout.push({
                 let mut msg = ColorString::new();
@@ -268,13 +445,18 @@ impl TargetIO {
         profile: &Profile,
         target_deps: &TargetDepsMap, 
     ) -> Self {
+        // prebuilt and external libraries are used as they are
+        if target.is_foreign() {
+            return Self {
+                output: target.output_path(layout, toolchain.target()),
+                artifacts: Vec::new(),
+                deps: target_deps[target].libs.clone(),
+            };
+        }
+
         let bin_type = target.kind.into();
 
-        let lang = if target.sources.iter().any(|v| Language::detect(&v).is_cxx()) {
-            Language::Cxx
-        } else {
-            Language::C
-        };
+        let lang = target.language();
 
         let output = layout.output_dir(&target.package).join(Build::output_name(
             &target.name, 
@@ -301,13 +483,64 @@ impl TargetDeps {
     fn new(
         cx: &Context,
         target: &Target,
+        link_ranks: &HashMap<Unit, (usize, usize)>,
     ) -> Self {
         let mut deps = TargetDeps::default();        
-        deps.collect(cx, target);
+        for link in target.links.iter() {
+            deps.links.push(link.to_string());
+        }
+        deps.search_paths.extend(target.search_paths.iter().cloned());
+
+        // the target itself is excluded, it can be reached again through a link group
+        let mut linked = vec![target.clone()];
+        deps.collect(cx, target, &mut linked);
+        linked.remove(0);
+        // Libraries are linked before the libraries they depend on, 
+        // members of a link group have the same rank and are kept next to each other
+        linked.sort_by_key(|v| {
+            let (rank, group) = link_ranks[&Unit::Target(v.clone())];
+            (std::cmp::Reverse(rank), group)
+        });
+        // a library is linked as a whole archive if it or any of its dependents asks for it
+        let whole_archive = |dep: &Target| {
+            dep.whole_archive || std::iter::once(target).chain(linked.iter())
+                .any(|v| v.whole_archive_deps.contains(&dep.full_name()))
+        };
+        let mut groups = BTreeMap::<usize, Vec<PathBuf>>::new();
+        for dep in linked.iter().filter(|v| v.kind.has_output()) {
+            let output = dep.output_path(cx.layout, cx.toolchain.target());
+            if dep.link_group {
+                let (_, group) = link_ranks[&Unit::Target(dep.clone())];
+                groups.entry(group).or_default().push(output.clone());
+            }
+            if dep.kind == TargetKind::Static && whole_archive(dep) {
+                deps.whole_archive.push(output.clone());
+            }
+            deps.libs.push(output);
+        }
+        deps.link_groups = groups.into_values().filter(|v| v.len() > 1).collect();
+        for dep in linked.iter() {
+            deps.pkg_config_files.extend(dep.pkg_config_files.iter().cloned());
+        }
+
+        // A system library must be linked after all libraries that need it, 
+        // so only the last occurrence of a duplicate is kept
+        let mut seen = BTreeSet::new();
+        deps.links.reverse();
+        deps.links.retain(|v| seen.insert(v.clone()));
+        deps.links.reverse();
+        let mut seen = BTreeSet::new();
+        deps.search_paths.retain(|v| seen.insert(v.clone()));
         // Add defines last so we can overwrite dep defines
         for include in target.includes.iter() {
             deps.includes.insert(include.to_path_buf());
         }
+        if let Some(dir) = target.version_header_dir(cx.layout) {
+            deps.includes.insert(dir);
+        }
+        if let Some(dir) = target.gen_include_dir(cx.layout) {
+            deps.includes.insert(dir);
+        }
         for define in target.defines.iter() {
             let (key, value) = std::ops::Deref::deref(define).clone();
             deps.defines.insert(key, value);
@@ -320,37 +553,156 @@ impl TargetDeps {
         &mut self,
         cx: &Context,
         target: &Target,
+        linked: &mut Vec<Target>,
     ) {
-        let target_triple = cx.toolchain.target();
-
+        // Usage requirements of direct dependencies apply regardless of visibility
+        let mut used = HashSet::new();
         for dep_name in target.depends.iter() {
-            let dep = cx.units.get(dep_name, &target.package);
+            match cx.units.get(dep_name, &target.package) {
+                Unit::Target(dep) => {
+                    self.collect_usage(cx, dep, &mut used);
+                    self.collect_link(cx, dep, linked);
+                }
+                Unit::Step(step) => self.collect_step(cx, step),
+            }
+        }
+    }
 
-            let target = if let Unit::Target(target) = dep {
-                target
-            } else {
-                continue;
-            };
+    // Files configured by a step are included from the directory they are written to
+    fn collect_step(&mut self, cx: &Context, step: &Step) {
+        if let Some(path) = step.configure_output(cx.layout) {
+            self.includes.insert(path.parent().unwrap().to_path_buf());
+        }
+    }
 
-            self.libs.push(target.output_path(cx.layout, target_triple));
-            
-            for include in target.includes.iter() {
-                if include.is_public() {
-                    self.includes.insert(include.to_path_buf());
-                }
+    // Includes, defines and compile flags of `target` and its public dependencies
+    fn collect_usage(
+        &mut self,
+        cx: &Context,
+        target: &Target,
+        used: &mut HashSet<Target>,
+    ) {
+        if !used.insert(target.clone()) {
+            return;
+        }
+        for include in target.includes.iter() {
+            if include.is_public() {
+                self.includes.insert(include.to_path_buf());
             }
+        }
+        if let Some(prefix) = target.external_prefix(cx.layout) {
+            self.includes.insert(prefix.join("include"));
+        }
+        if let Some(dir) = target.version_header_dir(cx.layout) {
+            self.includes.insert(dir);
+        }
+        if let Some(dir) = target.gen_include_dir(cx.layout) {
+            self.includes.insert(dir);
+        }
 
-            for define in target.defines.iter() {
-                if define.is_public() {
-                    let (key, value) = std::ops::Deref::deref(define).clone();
-                    self.defines.insert(key, value);
-                }
+        for define in target.defines.iter() {
+            if define.is_public() {
+                let (key, value) = std::ops::Deref::deref(define).clone();
+                self.defines.insert(key, value);
             }
+        }
+
+        if target.kind == TargetKind::Interface {
+            self.cc_flags.extend(target.options.cc_flags.iter().cloned());
+        }
 
-            self.collect(cx, target);
+        for dep_name in target.depends.iter().filter(|v| v.is_public()) {
+            match cx.units.get(dep_name, &target.package) {
+                Unit::Target(dep) => self.collect_usage(cx, dep, used),
+                Unit::Step(step) => self.collect_step(cx, step),
+            }
         }
     }
 
+    // Libraries required to link against `target`
+    fn collect_link(
+        &mut self,
+        cx: &Context,
+        target: &Target,
+        linked: &mut Vec<Target>,
+    ) {
+        if linked.contains(target) {
+            return;
+        }
+        linked.push(target.clone());
+
+        // static and interface libraries cannot carry their own dependencies,
+        // so all of them are forwarded, shared libraries only forward public ones
+        let forward_all = matches!(target.kind, TargetKind::Static | TargetKind::Interface);
+
+        for link in target.links.iter() {
+            if link.is_public() || forward_all {
+                self.links.push(link.to_string());
+            }
+        }
+        self.search_paths.extend(target.search_paths.iter().cloned());
+
+        if target.kind == TargetKind::Interface {
+            self.ld_flags.extend(target.options.ld_flags.iter().cloned());
+        }
+
+        for dep_name in target.depends.iter().filter(|v| v.is_public() || forward_all) {
+            if let Unit::Target(dep) = cx.units.get(dep_name, &target.package) {
+                self.collect_link(cx, dep, linked);
+            }
+        }
+    }
+
+}
+
+
+// Position of each unit on a link line, a unit ranks higher than all of its dependencies.
+// Members of a link group share a rank and group index, every other unit is its own group.
+fn link_ranks(graph: &Graph<Unit>, link_groups: &[Vec<Unit>]) -> HashMap<Unit, (usize, usize)> {
+    let mut groups = link_groups.to_vec();
+    let mut group_of = HashMap::new();
+    for (i, group) in groups.iter().enumerate() {
+        for unit in group {
+            group_of.insert(unit.clone(), i);
+        }
+    }
+    for unit in graph.nodes() {
+        if !group_of.contains_key(unit) {
+            group_of.insert(unit.clone(), groups.len());
+            groups.push(vec![unit.clone()]);
+        }
+    }
+
+    fn rank(
+        i: usize,
+        graph: &Graph<Unit>,
+        groups: &[Vec<Unit>],
+        group_of: &HashMap<Unit, usize>,
+        ranks: &mut Vec<Option<usize>>,
+    ) -> usize {
+        if let Some(r) = ranks[i] {
+            return r;
+        }
+        let mut r = 0;
+        for unit in groups[i].iter() {
+            for (dep, _) in graph.edges(unit) {
+                let j = group_of[dep];
+                if j != i {
+                    r = r.max(rank(j, graph, groups, group_of, ranks) + 1);
+                }
+            }
+        }
+        ranks[i] = Some(r);
+        r
+    }
+
+    let mut ranks = vec![None; groups.len()];
+    graph.nodes()
+        .map(|unit| {
+            let i = group_of[unit];
+            (unit.clone(), (rank(i, graph, &groups, &group_of, &mut ranks), i))
+        })
+        .collect()
 }
 
 
@@ -365,6 +717,8 @@ struct Output {
     src: PathBuf,
     dst: Option<PathBuf>,
     updated: bool,
+    // file name the output links to instead of copying `src`
+    symlink: Option<PathBuf>,
 }
 
 impl Outputs {
@@ -381,36 +735,296 @@ impl Outputs {
         let updated = output.did_link;
         let runtime_dst = target.runtime_path(cx.layout, cx.toolchain.target());
 
+        // versioned shared libraries are copied with the symlinks that point to them
+        let real = output.symlinks.first()
+            .map_or_else(|| output.path.clone(), |(link, file)| link.with_file_name(file));
+
         // output goes into runtime directory first, otherwise target directory
         self.outputs.lock().unwrap().push(Output{
             updated,
-            src: output.path.clone(), 
-            dst: runtime_dst.clone(), 
+            dst: runtime_dst.as_ref().map(|v| v.with_file_name(real.file_name().unwrap())), 
+            src: real, 
+            symlink: None,
         });
+        for (link, file) in output.symlinks.iter() {
+            self.outputs.lock().unwrap().push(Output{
+                updated,
+                src: link.clone(),
+                dst: runtime_dst.as_ref().map(|v| v.with_file_name(link.file_name().unwrap())),
+                symlink: Some(file.clone()),
+            });
+        }
 
         // debug info goes next to the target output file
         for artifact in output.extra.iter() {
             let ext = artifact.extension().and_then(|x| x.to_str()).unwrap();
-            if ext == Artifact::Pdb.ext() || ext == Artifact::Dsym.ext() {
+            if ext == Artifact::Pdb.ext() || ext == Artifact::Dsym.ext() || ext == Artifact::Debug.ext() {
                 self.outputs.lock().unwrap().push(Output{ 
                     dst: runtime_dst.as_ref().map(|v| v.with_extension(ext)), 
                     src: artifact.clone(),
                     updated,
+                    symlink: None,
                 });
             }
         }
     }
 
+    fn add_prebuilt(
+        &self, 
+        cx: &Context, 
+        target: &Target, 
+    ) {
+        if target.kind != TargetKind::Shared {
+            return;
+        }
+        // shared libraries are needed next to the executable at runtime
+        self.outputs.lock().unwrap().push(Output{
+            updated: true,
+            src: target.output_path(cx.layout, cx.toolchain.target()),
+            dst: target.runtime_path(cx.layout, cx.toolchain.target()),
+            symlink: None,
+        });
+    }
+
     fn copy_to(&self, dst: &Path) -> IResult<()> {
         for output in self.outputs.lock().unwrap().iter() {
             if output.updated {
-                std::fs::copy(
-                    &output.src, 
-                    output.dst.clone().unwrap_or_else(||
-                        dst.join(output.src.file_name().unwrap())),
-                )?;
+                let path = output.dst.clone()
+                    .unwrap_or_else(|| dst.join(output.src.file_name().unwrap()));
+                if let Some(file) = &output.symlink {
+                    paths::symlink(file, path)?;
+                } else {
+                    std::fs::copy(&output.src, path)?;
+                }
             }
         }
         Ok(())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TargetKind::{Static, Shared, Interface};
+
+    fn vis<T>(public: bool, value: T) -> PublicPrivate<T> {
+        if public { PublicPrivate::public(value) } else { PublicPrivate::private(value) }
+    }
+
+    // Target with a public and a private include, define and link named after itself
+    fn target(id: PackageId, name: &str, kind: TargetKind, depends: &[(&str, bool)]) -> Target {
+        Target::new(target_inner(id, name, kind, depends))
+    }
+
+    fn target_inner(id: PackageId, name: &str, kind: TargetKind, depends: &[(&str, bool)]) -> TargetInner {
+        TargetInner{
+            sources: if kind.has_output() { vec![PathBuf::from(format!("/demo/{name}.c"))] } else { Vec::new() },
+            depends: depends.iter().map(|(dep, public)| vis(*public, TargetName::new(id.name(), *dep))).collect(),
+            includes: vec![vis(true, format!("/{name}/public").into()), vis(false, format!("/{name}/private").into())],
+            defines: vec![vis(true, (format!("{name}_PUBLIC"), None)), vis(false, (format!("{name}_PRIVATE"), None))],
+            links: vec![vis(true, format!("{name}_public")), vis(false, format!("{name}_private"))],
+            ..TargetInner::new(name.into(), kind, id)
+        }
+    }
+
+    struct Seen {
+        includes: Vec<String>,
+        defines: Vec<String>,
+        libs: Vec<String>,
+        links: Vec<String>,
+        link_groups: Vec<Vec<String>>,
+        whole_archive: Vec<String>,
+        // units in the parallel build stages, every unit must be scheduled
+        scheduled: usize,
+    }
+
+    fn demo_id() -> PackageId {
+        PackageId::new("demo", "0.1.0", SourceId::new(PathBuf::from("/demo"))).unwrap()
+    }
+
+    // Dependency information of the last target in `targets`
+    fn collect_deps(targets: Vec<Target>) -> IResult<Seen> {
+        let pkg = Package::new(PackageInner{
+            id: demo_id(),
+            targets,
+            steps: Vec::new(),
+            dependencies: Vec::new(),
+            profiles: BTreeMap::new(),
+            warnings: Vec::new(),
+        });
+        let config = Config::default()?;
+        let profile = Profile::dev();
+        let layout = Layout::new("/demo", &profile, None);
+        let toolchain = Toolchain::default()?;
+        let packages = PackageMap::from_packages(&[pkg.clone()]);
+        let main_target = pkg.targets.last().unwrap();
+        let cx = Context::new(&config, &layout, &toolchain, &profile, &packages, &pkg, &[main_target.name.to_string()])?;
+
+        let deps = &cx.target_deps[main_target];
+        let names = |libs: &[PathBuf]| -> Vec<String> {
+            libs.iter()
+                .filter_map(|v| cx.units.with_output(v).and_then(Unit::as_target))
+                .map(|t| t.name.to_string())
+                .collect()
+        };
+        Ok(Seen{
+            includes: deps.includes.iter().map(|v| v.display().to_string()).collect(),
+            defines: deps.defines.keys().cloned().collect(),
+            libs: names(&deps.libs),
+            links: deps.links.clone(),
+            link_groups: deps.link_groups.iter().map(|v| names(v)).collect(),
+            whole_archive: names(&deps.whole_archive),
+            scheduled: cx.unit_graph.parallel_stages().map(|v| v.len()).sum(),
+        })
+    }
+
+    // Dependency information of `app` in `app -> upper -(upper_pub)-> mid -(mid_pub)-> leaf`,
+    // `upper` and `leaf` are static libraries
+    fn collect(upper_pub: bool, mid_kind: TargetKind, mid_pub: bool) -> Seen {
+        let id = demo_id();
+        collect_deps(vec![
+            target(id, "leaf", Static, &[]),
+            target(id, "mid", mid_kind, &[("leaf", mid_pub)]),
+            target(id, "upper", Static, &[("mid", upper_pub)]),
+            target(id, "app", TargetKind::Bin, &[("upper", false)]),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn transitive_propagation() {
+        // (upper -> mid public, mid kind, mid -> leaf public) =>
+        // (mid usage, leaf usage, mid linked, leaf linked, mid private links forwarded)
+        let matrix = [
+            ((true,  Static,    true ), (true,  true,  true,  true,  true )),
+            ((true,  Static,    false), (true,  false, true,  true,  true )),
+            ((true,  Shared,    true ), (true,  true,  true,  true,  false)),
+            ((true,  Shared,    false), (true,  false, true,  false, false)),
+            ((true,  Interface, true ), (true,  true,  false, true,  true )),
+            ((true,  Interface, false), (true,  false, false, true,  true )),
+            ((false, Static,    true ), (false, false, true,  true,  true )),
+            ((false, Static,    false), (false, false, true,  true,  true )),
+            ((false, Shared,    true ), (false, false, true,  true,  false)),
+            ((false, Shared,    false), (false, false, true,  false, false)),
+            ((false, Interface, true ), (false, false, false, true,  true )),
+            ((false, Interface, false), (false, false, false, true,  true )),
+        ];
+        for ((upper_pub, mid_kind, mid_pub), expected) in matrix {
+            let seen = collect(upper_pub, mid_kind, mid_pub);
+            let has = |v: &[String], s: &str| v.iter().any(|v| v == s);
+            let row = format!("upper_pub={upper_pub} mid={mid_kind:?} mid_pub={mid_pub}");
+
+            // direct dependencies only provide their public usage requirements
+            assert!(has(&seen.includes, "/upper/public") && has(&seen.defines, "upper_PUBLIC"), "{row}");
+            assert!(!has(&seen.includes, "/upper/private") && !has(&seen.defines, "upper_PRIVATE"), "{row}");
+            for name in ["mid", "leaf"] {
+                assert!(!has(&seen.includes, &format!("/{name}/private")), "{row}");
+                assert!(!has(&seen.defines, &format!("{name}_PRIVATE")), "{row}");
+            }
+
+            let (mid_usage, leaf_usage, mid_linked, leaf_linked, mid_private) = expected;
+            assert_eq!(has(&seen.includes, "/mid/public"), mid_usage, "{row}");
+            assert_eq!(has(&seen.defines, "mid_PUBLIC"), mid_usage, "{row}");
+            assert_eq!(has(&seen.includes, "/leaf/public"), leaf_usage, "{row}");
+            assert_eq!(has(&seen.defines, "leaf_PUBLIC"), leaf_usage, "{row}");
+
+            // static libraries forward everything needed to link them
+            assert!(has(&seen.libs, "upper") && has(&seen.links, "upper_private"), "{row}");
+            assert_eq!(has(&seen.libs, "mid"), mid_linked, "{row}");
+            assert!(has(&seen.links, "mid_public"), "{row}");
+            assert_eq!(has(&seen.links, "mid_private"), mid_private, "{row}");
+            assert_eq!(has(&seen.libs, "leaf"), leaf_linked, "{row}");
+            assert_eq!(has(&seen.links, "leaf_public"), leaf_linked, "{row}");
+            assert_eq!(has(&seen.links, "leaf_private"), leaf_linked, "{row}");
+        }
+    }
+
+    #[test]
+    fn link_order() {
+        // app -> (left, right) -> base, app -> base
+        let id = demo_id();
+        let seen = collect_deps(vec![
+            target(id, "base", Static, &[]),
+            target(id, "left", Static, &[("base", false)]),
+            target(id, "right", Static, &[("base", false)]),
+            target(id, "app", TargetKind::Bin, &[("base", false), ("left", false), ("right", false)]),
+        ]).unwrap();
+        assert_eq!(seen.libs, ["left", "right", "base"]);
+        assert!(seen.link_groups.is_empty());
+    }
+
+    #[test]
+    fn link_groups() {
+        // app -> first <-> second -> base
+        let id = demo_id();
+        let grouped = |name, depends| Target::new(TargetInner{
+            link_group: true,
+            ..target_inner(id, name, Static, depends)
+        });
+        let seen = collect_deps(vec![
+            target(id, "base", Static, &[]),
+            grouped("first", &[("second", false)]),
+            grouped("second", &[("first", false), ("base", false)]),
+            target(id, "app", TargetKind::Bin, &[("first", false)]),
+        ]).unwrap();
+        assert_eq!(seen.libs.len(), 3);
+        assert_eq!(seen.libs[2], "base");
+        assert_eq!(seen.link_groups.len(), 1);
+        assert_eq!(seen.link_groups[0], seen.libs[..2]);
+        assert_eq!(seen.scheduled, 4);
+
+        // app -> first <-> second <-> third, first <-> third
+        let seen = collect_deps(vec![
+            grouped("first", &[("second", false), ("third", false)]),
+            grouped("second", &[("first", false), ("third", false)]),
+            grouped("third", &[("second", false), ("first", false)]),
+            target(id, "app", TargetKind::Bin, &[("first", false)]),
+        ]).unwrap();
+        assert_eq!(seen.libs.len(), 3);
+        assert_eq!(seen.link_groups.len(), 1);
+        assert_eq!(seen.link_groups[0].len(), 3);
+        assert_eq!(seen.scheduled, 4);
+
+        // a <-> b <-> c
+        let seen = collect_deps(vec![
+            grouped("a", &[("b", false)]),
+            grouped("b", &[("a", false), ("c", false)]),
+            grouped("c", &[("b", false)]),
+            target(id, "app", TargetKind::Bin, &[("a", false)]),
+        ]).unwrap();
+        assert_eq!(seen.link_groups.len(), 1);
+        assert_eq!(seen.link_groups[0].len(), 3);
+        assert_eq!(seen.scheduled, 4);
+
+        // the same cycle without `link-group` is an error
+        let err = collect_deps(vec![
+            target(id, "first", Static, &[("second", false)]),
+            target(id, "second", Static, &[("first", false)]),
+            target(id, "app", TargetKind::Bin, &[("first", false)]),
+        ]).err().unwrap();
+        assert!(err.to_string().contains("Dependency cycle"), "{err}");
+    }
+
+    #[test]
+    fn whole_archive() {
+        // app -> (plugins -> plugin), registry, plain
+        let id = demo_id();
+        let seen = collect_deps(vec![
+            target(id, "plugin", Static, &[]),
+            Target::new(TargetInner{
+                whole_archive_deps: vec![TargetName::new("demo", "plugin")],
+                ..target_inner(id, "plugins", Static, &[("plugin", false)])
+            }),
+            Target::new(TargetInner{
+                whole_archive: true,
+                ..target_inner(id, "registry", Static, &[])
+            }),
+            target(id, "plain", Static, &[]),
+            target(id, "app", TargetKind::Bin, &[("plugins", false), ("registry", false), ("plain", false)]),
+        ]).unwrap();
+        assert_eq!(seen.libs.len(), 4);
+        let mut whole_archive = seen.whole_archive.clone();
+        whole_archive.sort();
+        assert_eq!(whole_archive, ["plugin", "registry"]);
+    }
+}
@@ -0,0 +1,532 @@
+use crate::cc::{Crt, Language, OptLevel, Tool, host_triple};
+use crate::core::{Context, Target, TargetKind, Unit, fingerprint::DepInfo};
+use crate::utils::{IResult, MsgWriter, paths, BinarySerialize};
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use serde::{Serialize, Deserialize};
+use anyhow::Context as _;
+use filetime::FileTime;
+
+
+/// Build system used by an external project
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExternalKind {
+    Cmake,
+    Make,
+    Autotools,
+    // Rust crate built as a `staticlib`/`cdylib`
+    Cargo,
+}
+
+/// Third-party project that is built with its own build system
+#[derive(Debug, Hash)]
+pub struct External {
+    pub kind: ExternalKind,
+    // root directory of the project's source tree
+    pub path: PathBuf,
+    // name of the installed library (without `lib` prefix or extension)
+    pub lib: String,
+    // extra arguments passed when configuring the project
+    pub args: Vec<String>,
+}
+
+
+/// Configure, build and install the external project of `target` into its staging prefix
+pub fn build<E: Write>(
+    cx: &Context,
+    target: &Target,
+    mut stderr: MsgWriter<E>,
+) -> IResult<()> {
+    let external = target.external.as_ref().unwrap();
+    let dir = cx.layout.external_dir(&target.package).join(target.name);
+    let build_dir = dir.join("build");
+    let prefix = target.external_prefix(cx.layout).unwrap();
+    let log = dir.join("build.log");
+    paths::create_dir_all(&build_dir)?;
+    paths::create_dir_all(&prefix)?;
+    paths::write(&log, b"")?;
+
+    // The project is rebuilt when any file in its source tree changes. The dep-info is written
+    // before building, so its modification time is the start of the build like for other targets
+    let dep_info_path = target.dep_info_path(cx.layout);
+    let last_build = paths::mtime(&dep_info_path).ok();
+    let sources = source_files(external)?;
+    let mut dep_info = DepInfo::default();
+    for file in sources.iter() {
+        dep_info.add_target_relative(file.clone());
+    }
+    paths::write_create_all(&dep_info_path, dep_info.to_bytes())?;
+
+    let env = Env::new(cx)?;
+    let shared = target.kind == TargetKind::Shared;
+    let triple = cx.toolchain.target();
+    let cross = triple != host_triple();
+    let commands = match external.kind {
+        ExternalKind::Cmake => {
+            let build_type = match (cx.profile.is_optimized(), cx.profile.debug) {
+                (false, _) => "Debug",
+                (true, true) => "RelWithDebInfo",
+                (true, false) => "Release",
+            };
+            let mut configure = Command::new("cmake");
+            configure
+                .arg("-S").arg(&external.path)
+                .arg("-B").arg(&build_dir)
+                .arg(define("CMAKE_INSTALL_PREFIX", &prefix))
+                .arg(define("CMAKE_INSTALL_LIBDIR", "lib"))
+                .arg(define("CMAKE_BUILD_TYPE", build_type))
+                .arg(define("BUILD_SHARED_LIBS", if shared { "ON" } else { "OFF" }))
+                .arg(define("CMAKE_C_COMPILER", &env.cc))
+                .arg(define("CMAKE_AR", &env.ar))
+                .arg(define("CMAKE_C_FLAGS", &env.cflags));
+            if cross {
+                configure.args(cmake_cross_defines(triple));
+            }
+            if let Some(cxx) = &env.cxx {
+                configure
+                    .arg(define("CMAKE_CXX_COMPILER", cxx))
+                    .arg(define("CMAKE_CXX_FLAGS", &env.cxxflags));
+            }
+            configure.args(&external.args);
+
+            let mut build = Command::new("cmake");
+            build.arg("--build").arg(&build_dir)
+                .arg("--config").arg(build_type)
+                .arg("--parallel").arg(jobs().to_string());
+
+            let mut install = Command::new("cmake");
+            install.arg("--install").arg(&build_dir)
+                .arg("--config").arg(build_type);
+
+            vec![configure, build, install]
+        }
+        ExternalKind::Autotools => {
+            let mut configure = Command::new("sh");
+            configure
+                .arg(external.path.join("configure"))
+                .arg(format!("--prefix={}", prefix.display()))
+                .arg(format!("--libdir={}", prefix.join("lib").display()))
+                .args(if shared {
+                    ["--enable-shared", "--disable-static"]
+                } else {
+                    ["--enable-static", "--disable-shared"]
+                });
+            if cross {
+                configure
+                    .arg(format!("--host={}", autotools_triple(triple)))
+                    .arg(format!("--build={}", autotools_triple(host_triple())));
+            }
+            configure.args(&external.args);
+            env.apply(&mut configure);
+
+            let mut build = Command::new("make");
+            build.arg(format!("-j{}", jobs()));
+
+            let mut install = Command::new("make");
+            install.arg("install");
+
+            vec![configure, build, install]
+        }
+        ExternalKind::Make => {
+            // plain makefiles usually build in place, so they build in a copy of the source tree
+            copy_tree(&sources, &external.path, &build_dir, last_build)?;
+
+            let mut build = Command::new("make");
+            build.arg(format!("-j{}", jobs()))
+                .args(&external.args);
+            env.apply(&mut build);
+
+            let mut install = Command::new("make");
+            install.arg("install")
+                .arg(format!("PREFIX={}", prefix.display()))
+                .args(&external.args);
+            env.apply(&mut install);
+
+            vec![build, install]
+        }
+        ExternalKind::Cargo => {
+            let mut build = Command::new(std::env::var_os("CARGO").unwrap_or("cargo".into()));
+            build.arg("rustc")
+                .arg("--lib")
+                .arg("--manifest-path").arg(external.path.join("Cargo.toml"))
+                .arg("--target").arg(cx.toolchain.target())
+                .arg("--profile").arg(cargo_profile(cx))
+                .arg("--target-dir").arg(&build_dir)
+                .arg("--crate-type").arg(if shared { "cdylib" } else { "staticlib" })
+                .args(&external.args)
+                .args(["--", "--print", "native-static-libs"]);
+            // rust links the C runtime like its default for the target, unless `static-crt` is set
+            if !shared && target.options.crt == Crt::Static {
+                build.args(["-C", "target-feature=+crt-static"]);
+            }
+            env.apply(&mut build);
+            vec![build]
+        }
+    };
+
+    for mut cmd in commands {
+        cmd.current_dir(&build_dir);
+        if let Err(e) = run(&mut cmd, &log) {
+            drop(writeln!(stderr, "{}", String::from_utf8_lossy(&paths::read_bytes(&log)?)));
+            return Err(e.context(format!(
+                "failed to build external project `{}`, see `{}`",
+                target.full_name(),
+                log.display()
+            )));
+        }
+    }
+
+    if external.kind == ExternalKind::Cargo {
+        install_cargo_artifacts(cx, target, &build_dir, &prefix, &log)?;
+    }
+
+    let output = target.output_path(cx.layout, cx.toolchain.target());
+    if !output.exists() {
+        anyhow::bail!(
+            "external project `{}` did not install `{}`",
+            target.full_name(),
+            output.display()
+        )
+    }
+
+    Ok(())
+}
+
+
+/// Native libraries that consumers of a Rust `staticlib` must link, as reported by rustc.
+/// Apple frameworks are named like `framework=CoreFoundation`
+pub fn native_libs(cx: &Context, target: &Target) -> IResult<Vec<String>> {
+    let Some(path) = native_libs_file(cx, target) else {
+        return Ok(Vec::new());
+    };
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(paths::read_string(&path)?.lines().map(|v| v.to_string()).collect())
+}
+
+
+/// File that the native libraries of a Rust `staticlib` are written to, an output of the target
+pub fn native_libs_file(cx: &Context, target: &Target) -> Option<PathBuf> {
+    match &target.external {
+        Some(external) if external.kind == ExternalKind::Cargo && target.kind == TargetKind::Static => {
+            Some(cx.layout.external_dir(&target.package).join(target.name).join("native-static-libs"))
+        }
+        _ => None,
+    }
+}
+
+
+/// Whether `target` links a Rust `staticlib` that links the C runtime dynamically,
+/// which is rust's default for most targets unless the library sets `static-crt`
+pub fn links_shared_crt(cx: &Context, target: &Target) -> bool {
+    cx.target_deps[target].libs.iter()
+        .filter_map(|lib| cx.units.with_output(lib).and_then(Unit::as_target))
+        .any(|dep| native_libs_file(cx, dep).is_some() && dep.options.crt != Crt::Static)
+}
+
+
+/// Name of the library of the Rust crate at `path`
+pub fn cargo_lib_name(path: &Path) -> IResult<String> {
+    let manifest_path = path.join("Cargo.toml");
+    let manifest: toml::Value = paths::read_string(&manifest_path)?
+        .parse()
+        .with_context(|| format!("failed to parse `{}`", manifest_path.display()))?;
+    let name = manifest.get("lib")
+        .and_then(|v| v.get("name"))
+        .or_else(|| manifest.get("package").and_then(|v| v.get("name")))
+        .and_then(|v| v.as_str());
+    match name {
+        Some(v) => Ok(v.replace('-', "_")),
+        None => anyhow::bail!("no package name found in `{}`", manifest_path.display()),
+    }
+}
+
+
+// Files in the source tree of the project
+fn source_files(external: &External) -> IResult<Vec<PathBuf>> {
+    let mut files = paths::walk_files(&external.path)?;
+    // artifacts of cargo builds outside of ccargo are not sources
+    if external.kind == ExternalKind::Cargo {
+        let cargo_target = external.path.join("target");
+        files.retain(|file| !file.starts_with(&cargo_target));
+    }
+    Ok(files)
+}
+
+
+// Cross builds tell cmake the system and processor it builds for, the compilers are already set.
+// The arguments of the project come later, so a `CMAKE_TOOLCHAIN_FILE` can still override them
+fn cmake_cross_defines(target: &str) -> Vec<OsString> {
+    let system = if target.contains("windows") {
+        "Windows"
+    } else if target.contains("apple-ios") {
+        "iOS"
+    } else if target.contains("apple") {
+        "Darwin"
+    } else if target.contains("android") {
+        "Android"
+    } else if target.contains("linux") {
+        "Linux"
+    } else if target.contains("freebsd") {
+        "FreeBSD"
+    } else {
+        "Generic"
+    };
+    let arch = target.split('-').next().unwrap_or_default();
+    vec![define("CMAKE_SYSTEM_NAME", system), define("CMAKE_SYSTEM_PROCESSOR", arch)]
+}
+
+
+// Rust target triples are understood by `config.sub`, except for mingw
+fn autotools_triple(target: &str) -> String {
+    match target.strip_suffix("-pc-windows-gnu") {
+        Some(arch) => format!("{arch}-w64-mingw32"),
+        None => target.to_string(),
+    }
+}
+
+
+// Cargo profile matching the active profile
+fn cargo_profile(cx: &Context) -> &'static str {
+    match cx.profile.dir_name.as_str() {
+        "debug" => "dev",
+        v => v,
+    }
+}
+
+
+// Copy the library built by cargo into the staging prefix and remember its native libraries
+fn install_cargo_artifacts(
+    cx: &Context,
+    target: &Target,
+    build_dir: &Path,
+    prefix: &Path,
+    log: &Path,
+) -> IResult<()> {
+    let external = target.external.as_ref().unwrap();
+    let triple = cx.toolchain.target();
+    let out_dir = build_dir.join(triple).join(match cargo_profile(cx) {
+        "dev" => "debug",
+        v => v,
+    });
+    let msvc = triple.contains("msvc");
+    let name = &external.lib;
+    let output = target.output_path(cx.layout, triple);
+    let shared = target.kind == TargetKind::Shared;
+    let built = out_dir.join(match (shared, triple) {
+        (false, t) if t.contains("msvc") => format!("{name}.lib"),
+        (true, t) if t.contains("windows") => format!("{name}.dll"),
+        (true, t) if t.contains("apple") => format!("lib{name}.dylib"),
+        (true, _) => format!("lib{name}.so"),
+        (false, _) => format!("lib{name}.a"),
+    });
+    paths::create_dir_all(prefix.join("lib"))?;
+    copy(&built, &output)?;
+    // msvc links against the import library of a dll
+    if msvc && shared {
+        copy(&out_dir.join(format!("{name}.dll.lib")), &output.with_extension("lib"))?;
+    }
+
+    // `note: native-static-libs: -lgcc_s -lc` (not printed when cargo did not rebuild the crate,
+    // then the libraries of the previous build are kept). Dependents relink when the file changes
+    let Some(path) = native_libs_file(cx, target) else {
+        return Ok(());
+    };
+    let contents = String::from_utf8_lossy(&paths::read_bytes(log)?).into_owned();
+    if let Some(line) = contents.lines().rev().find_map(|v| v.split_once("native-static-libs:")) {
+        paths::write(&path, parse_native_libs(line.1).join("\n"))?;
+    } else if !path.exists() {
+        paths::write(&path, b"")?;
+    }
+    Ok(())
+}
+
+
+// Libraries of rustc's `native-static-libs` (e.g. `-lc -framework Security` or `kernel32.lib`)
+fn parse_native_libs(line: &str) -> Vec<String> {
+    let mut libs = Vec::new();
+    let mut args = line.split_whitespace();
+    while let Some(arg) = args.next() {
+        if arg == "-framework" {
+            libs.extend(args.next().map(|v| format!("framework={v}")));
+        } else if let Some(lib) = arg.strip_prefix("-l").or_else(|| arg.strip_suffix(".lib")) {
+            libs.push(lib.to_string());
+        }
+    }
+    libs
+}
+
+
+fn copy(from: &Path, to: &Path) -> IResult<()> {
+    std::fs::copy(from, to)
+        .with_context(|| format!("failed to copy `{}` to `{}`", from.display(), to.display()))?;
+    Ok(())
+}
+
+
+// Tools and flags from the active toolchain and profile, passed to the external build system and steps
+pub(crate) struct Env {
+    cc: PathBuf,
+    cxx: Option<PathBuf>,
+    ar: PathBuf,
+    cflags: String,
+    cxxflags: String,
+}
+
+impl Env {
+    pub(crate) fn new(cx: &Context) -> IResult<Self> {
+        let Some(c) = cx.toolchain.tools_for(Language::C) else {
+            anyhow::bail!("no C compiler was found for target `{}`", cx.toolchain.target())
+        };
+        let cxx = cx.toolchain.tools_for(Language::Cxx);
+        let mut profile_flags = Vec::new();
+        if !c.cc.family().is_msvc() {
+            profile_flags.push(match cx.profile.opt_level {
+                OptLevel::O0 => "-O0",
+                OptLevel::O1 => "-O1",
+                OptLevel::O2 => "-O2",
+                OptLevel::O3 => "-O3",
+                OptLevel::Os => "-Os",
+                OptLevel::Oz => "-Oz",
+            });
+            if cx.profile.debug {
+                profile_flags.push("-g");
+            }
+        }
+        let flags = |tool: &Tool| {
+            tool.args().iter()
+                .map(|v| v.to_string_lossy().into_owned())
+                .chain(profile_flags.iter().map(|v| v.to_string()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        Ok(Self {
+            cc: c.cc.path().to_path_buf(),
+            cxx: cxx.map(|v| v.cc.path().to_path_buf()),
+            ar: c.ar.path().to_path_buf(),
+            cflags: flags(&c.cc),
+            cxxflags: cxx.map(|v| flags(&v.cc)).unwrap_or_default(),
+        })
+    }
+
+    pub(crate) fn apply(&self, cmd: &mut Command) {
+        cmd.env("CC", &self.cc)
+            .env("AR", &self.ar)
+            .env("CFLAGS", &self.cflags);
+        if let Some(cxx) = &self.cxx {
+            cmd.env("CXX", cxx)
+                .env("CXXFLAGS", &self.cxxflags);
+        }
+    }
+}
+
+
+// Run `cmd`, appending its output to `log`
+fn run(cmd: &mut Command, log: &Path) -> IResult<()> {
+    let output = cmd.output()
+        .with_context(|| format!("failed to run `{}`", cmd.get_program().to_string_lossy()))?;
+    let mut contents = paths::read_bytes(log)?;
+    writeln!(contents, "> {cmd:?}")?;
+    contents.extend_from_slice(&output.stdout);
+    contents.extend_from_slice(&output.stderr);
+    paths::write(log, contents)?;
+    if !output.status.success() {
+        anyhow::bail!("command {cmd:?} failed with {}", output.status)
+    }
+    Ok(())
+}
+
+
+// Copy `files` from `src` into `dst`, only copying the files that changed since `last_build`.
+// Copies are newer than the objects built from their previous contents, so make rebuilds them
+fn copy_tree(files: &[PathBuf], src: &Path, dst: &Path, last_build: Option<FileTime>) -> IResult<()> {
+    for file in files {
+        let to = dst.join(file.strip_prefix(src).unwrap());
+        if let Some(last_build) = last_build {
+            if to.exists() && paths::mtime(file)? <= last_build {
+                continue;
+            }
+        }
+        paths::create_dir_all(to.parent().unwrap())?;
+        std::fs::copy(file, &to)
+            .with_context(|| format!("failed to copy `{}`", file.display()))?;
+    }
+    Ok(())
+}
+
+
+fn define(key: &str, value: impl AsRef<std::ffi::OsStr>) -> OsString {
+    let mut arg = OsString::from(format!("-D{key}="));
+    arg.push(value);
+    arg
+}
+
+
+fn jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |v| v.get())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_static_libs() {
+        assert_eq!(parse_native_libs(" -lgcc_s -lutil -lc"), ["gcc_s", "util", "c"]);
+        assert_eq!(parse_native_libs(" kernel32.lib ntdll.lib /defaultlib:msvcrt"), ["kernel32", "ntdll"]);
+        assert_eq!(
+            parse_native_libs(" -framework CoreFoundation -liconv -framework Security -lSystem"),
+            ["framework=CoreFoundation", "iconv", "framework=Security", "System"]
+        );
+    }
+
+    #[test]
+    fn cross_settings() {
+        let defines = |target| cmake_cross_defines(target).into_iter()
+            .map(|v| v.into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(defines("aarch64-unknown-linux-gnu"), ["-DCMAKE_SYSTEM_NAME=Linux", "-DCMAKE_SYSTEM_PROCESSOR=aarch64"]);
+        assert_eq!(defines("x86_64-pc-windows-msvc"), ["-DCMAKE_SYSTEM_NAME=Windows", "-DCMAKE_SYSTEM_PROCESSOR=x86_64"]);
+        assert_eq!(defines("aarch64-apple-darwin")[0], "-DCMAKE_SYSTEM_NAME=Darwin");
+        assert_eq!(defines("aarch64-apple-ios")[0], "-DCMAKE_SYSTEM_NAME=iOS");
+        assert_eq!(defines("thumbv7em-none-eabihf")[0], "-DCMAKE_SYSTEM_NAME=Generic");
+
+        assert_eq!(autotools_triple("x86_64-pc-windows-gnu"), "x86_64-w64-mingw32");
+        assert_eq!(autotools_triple("armv7-unknown-linux-gnueabihf"), "armv7-unknown-linux-gnueabihf");
+    }
+
+    #[test]
+    fn sources_and_copies() {
+        let dir = std::env::temp_dir().join("ccargo-test-external-sources");
+        drop(std::fs::remove_dir_all(&dir));
+        let (src, dst) = (dir.join("src"), dir.join("build"));
+        paths::write_create_all(src.join("Makefile"), "all:").unwrap();
+        paths::write_create_all(src.join("lib").join("a.c"), "int a;").unwrap();
+        paths::write_create_all(src.join("target").join("debug").join("liba.a"), "").unwrap();
+        paths::write_create_all(src.join("Cargo.toml"), "").unwrap();
+
+        let mut external = External{kind: ExternalKind::Make, path: src.clone(), lib: "a".into(), args: Vec::new()};
+        let files = source_files(&external).unwrap();
+        assert_eq!(files.len(), 4);
+        external.kind = ExternalKind::Cargo;
+        assert_eq!(source_files(&external).unwrap(), [src.join("Cargo.toml"), src.join("Makefile"), src.join("lib").join("a.c")]);
+
+        // Everything is copied for the first build, then only files that changed since the last build
+        let files = [src.join("Makefile"), src.join("lib").join("a.c")];
+        copy_tree(&files, &src, &dst, None).unwrap();
+        assert_eq!(paths::read_string(dst.join("lib").join("a.c")).unwrap(), "int a;");
+        let last_build = FileTime::from_unix_time(paths::mtime(&files[0]).unwrap().unix_seconds() + 10, 0);
+        paths::write(dst.join("Makefile"), "old").unwrap();
+        paths::write(&files[1], "int b;").unwrap();
+        filetime::set_file_mtime(&files[1], FileTime::from_unix_time(last_build.unix_seconds() + 10, 0)).unwrap();
+        copy_tree(&files, &src, &dst, Some(last_build)).unwrap();
+        assert_eq!(paths::read_string(dst.join("Makefile")).unwrap(), "old");
+        assert_eq!(paths::read_string(dst.join("lib").join("a.c")).unwrap(), "int b;");
+        drop(std::fs::remove_dir_all(&dir));
+    }
+}
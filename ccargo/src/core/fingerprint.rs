@@ -1,5 +1,5 @@
 use crate::cc::{dep_info, Object};
-use crate::core::{Unit, Context, Target, TargetName, Step};
+use crate::core::{Unit, Context, Target, TargetName, Step, external};
 use crate::utils::{IResult, BinaryReader, BinaryWriter, BinarySerialize, BinaryDeserialize, paths, cached_mtime, to_hex, hash_u64};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
@@ -124,6 +124,13 @@ pub fn prepare(
         state.link = true;
         println!("{}", r.err().unwrap());
     }
+
+    // Objects compiled with a different configuration cannot be reused
+    if let Unit::Target(target) = unit {
+        if !same_configuration(&fingerprint, fingerprint_path) {
+            state.files.extend(target.all_sources(cx.layout, cx.toolchain.target()));
+        }
+    }
     println!("Target `{}` - dirty", unit.full_name());
     
     if fingerprint_path.exists() {
@@ -182,7 +189,7 @@ fn calculate(
     fingerprint.check_filesystem(
         pkg_root,
         &target_root,
-        sources,
+        &sources,
         state,
     )?;
     
@@ -193,32 +200,71 @@ fn calculate(
 
 
 /// Calculates the fingerprint for a `Target` `unit`.
-fn calculate_target<'a>(
-    target: &'a Target,
+fn calculate_target(
+    target: &Target,
     cx: &Context, 
     pkg_root: &Path,
     target_root: &Path,
     state: &mut FingerprintState,
-) -> IResult<(&'a [PathBuf], Fingerprint)> {
+) -> IResult<(Vec<PathBuf>, Fingerprint)> {
     let deps = {
         let mut deps = Vec::new();
         let target_deps = &cx.target_deps[target];
+        let output = target.output_path(cx.layout, cx.toolchain.target());
         for dep_path in target_deps.libs.iter() {
             if let Some(dep_unit) = cx.units.with_output(dep_path) {
-                deps.push(DepFingerprint::new(cx, dep_unit, state)?);
+                // members of a link group depend on each other, but are archived independently
+                let cyclic = matches!(dep_unit, Unit::Target(dep) if cx.target_deps[dep].libs.contains(&output));
+                if !cyclic {
+                    deps.push(DepFingerprint::new(cx, dep_unit, state)?);
+                }
             }
         }
         deps.sort_by(|a, b| a.pkg_id.cmp(&b.pkg_id));
         deps
     };
 
-    let local = {
+    let mut local = {
         let dep_info = target.dep_info_path(cx.layout);
         let dep_info = dep_info.strip_prefix(&target_root).unwrap().to_path_buf();
         vec![LocalFingerprint::CheckDepInfo{ dep_info, check_all: true }]
     };
 
+    // Libraries that are not built only matter to dependents through their contents
+    if !target.kind.has_output() || target.prebuilt.is_some() {
+        // A replaced prebuilt library or `.pc` file is detected by its contents
+        let inputs = target.prebuilt.iter().chain(target.pkg_config_files.iter());
+        let contents = inputs
+            .map(|path| Ok(hash_u64(&paths::read_bytes(path)?)))
+            .collect::<IResult<Vec<_>>>()?;
+        return Ok((target.sources.clone(), Fingerprint{
+            deps,
+            local: Vec::new(),
+            outputs: Vec::new(),
+            fs_status: FsStatus::Stale,
+            compiler_hash: 0,
+            target_hash: hash_u64(&(target.stable_hash(pkg_root), contents)),
+            profile_hash: 0,
+            memoized_hash: Mutex::default(),
+        }));
+    }
+
     let io = &cx.target_io[target];
+    // System libraries are read from `.pc` files, which are inputs like the target's own files
+    let pkg_config_files = &cx.target_deps[target].pkg_config_files;
+    if !pkg_config_files.is_empty() {
+        local.push(LocalFingerprint::RerunIfChanged{
+            output: io.output.strip_prefix(target_root).unwrap_or(&io.output).to_path_buf(),
+            paths: pkg_config_files.clone(),
+        });
+    }
+    // Changing the exported symbols only requires relinking
+    if let Some(exports) = &target.exports {
+        local.push(LocalFingerprint::RerunIfChanged{
+            output: io.output.strip_prefix(target_root).unwrap_or(&io.output).to_path_buf(),
+            paths: vec![exports.strip_prefix(pkg_root).unwrap_or(exports).to_path_buf()],
+        });
+    }
     let mut outputs = Vec::new();
     outputs.push(io.output.clone());
     for a in io.artifacts.iter() {
@@ -226,14 +272,34 @@ fn calculate_target<'a>(
             outputs.push(io.output.with_extension(a.ext()));
         }
     }
+    // Dependents link the native libraries of a rust library, so they are rebuilt when they change
+    outputs.extend(external::native_libs_file(cx, target));
+
+    // Flags inherited from dependencies affect compilation as much as the target's own
+    let target_hash = {
+        let target_deps = &cx.target_deps[target];
+        hash_u64(&(
+            target.stable_hash(pkg_root),
+            &target_deps.includes,
+            &target_deps.defines,
+            &target_deps.links,
+            &target_deps.search_paths,
+            &target_deps.link_groups,
+            &target_deps.whole_archive,
+            &target_deps.cc_flags,
+            &target_deps.ld_flags,
+            target.step_directives(cx)?,
+            external::links_shared_crt(cx, target),
+        ))
+    };
 
-    Ok((&target.sources, Fingerprint{
+    Ok((target.all_sources(cx.layout, cx.toolchain.target()), Fingerprint{
         deps,
         local,
         outputs,
         fs_status: FsStatus::Stale,
-        compiler_hash: hash_u64(cx.toolchain),
-        target_hash: hash_u64(&target.stable_hash(pkg_root)),
+        compiler_hash: hash_u64(&(cx.toolchain, cx.linker(target))),
+        target_hash,
         profile_hash: hash_u64(cx.profile),
         memoized_hash: Mutex::default(),
     }))
@@ -241,13 +307,13 @@ fn calculate_target<'a>(
 
 
 /// Calculates the fingerprint for a `Step` `unit`.
-fn calculate_step<'a>(
-    step: &'a Step,
+fn calculate_step(
+    step: &Step,
     cx: &Context, 
     pkg_root: &Path,
     target_root: &Path,
     state: &mut FingerprintState,
-) -> IResult<(&'a [PathBuf], Fingerprint)> {
+) -> IResult<(Vec<PathBuf>, Fingerprint)> {
     let deps = {
         let mut deps = Vec::new();
         for input in step.inputs.iter() {
@@ -272,16 +338,21 @@ fn calculate_step<'a>(
     let outputs = {
         let mut outputs = step.outputs.clone();
         outputs.push(step.output_path(cx.layout));
+        outputs.extend(step.configure_output(cx.layout));
         outputs
     };
 
-    Ok((&step.inputs, Fingerprint{
+    // Configured files change with the values substituted into them, not only with the template
+    let contents = step.configure_contents(cx)?;
+
+    Ok((step.inputs.clone(), Fingerprint{
         deps,
         local,
         outputs,
         fs_status: FsStatus::Stale,
-        compiler_hash: 0,
-        target_hash: hash_u64(&step.stable_hash(pkg_root)),
+        // steps are given `CC`/`CXX`/`AR` of the toolchain
+        compiler_hash: hash_u64(cx.toolchain),
+        target_hash: hash_u64(&(step.stable_hash(cx, pkg_root)?, contents)),
         profile_hash: hash_u64(cx.profile),
         memoized_hash: Mutex::default(),
     }))
@@ -532,9 +603,7 @@ impl LocalFingerprint {
                     return Ok(Some(StaleItem::MissingFile(dep_info)));
                 };
 
-                let paths = info.files.iter()
-                    .map(|(t, p)| t.path(p, pkg_root, target_root))
-                    .collect::<Vec<_>>();
+                let paths = info.files(pkg_root, target_root);
                 
                 let dep_info_mtime = match cached_mtime::mtime(&dep_info) {
                     Ok(mtime) => mtime,
@@ -552,6 +621,17 @@ impl LocalFingerprint {
                     }
                 }
 
+                for (var, previous) in info.env {
+                    let current = std::env::var(&var).ok();
+                    if current != previous {
+                        let stale = StaleItem::ChangedEnv{ var, previous, current };
+                        if !check_all {
+                            return Ok(Some(stale));
+                        }
+                        items.push(stale);
+                    }
+                }
+
                 for obj in info.objects {
                     let src = &paths[obj.file as usize];
                     for input in obj.inputs.iter() {
@@ -640,6 +720,27 @@ fn compare_old_fingerprint(
 }
 
 
+/// Whether the compiler, target and profile are the same as in the fingerprint written to disk
+fn same_configuration(
+    fingerprint: &Fingerprint, 
+    fingerprint_path: &Path,
+) -> bool {
+    let old_bytes = match paths::read_bytes(fingerprint_path.with_extension("bin")) {
+        Ok(v) => v,
+        Err(..) => return false,
+    };
+    let mut r = BinaryReader(&old_bytes);
+    match Fingerprint::deserialize(&mut r) {
+        Some(old) => {
+            fingerprint.compiler_hash == old.compiler_hash
+                && fingerprint.target_hash == old.target_hash
+                && fingerprint.profile_hash == old.profile_hash
+        }
+        None => false,
+    }
+}
+
+
 enum StaleItem {
     List(Vec<StaleItem>),
     MissingFile(PathBuf),
@@ -649,6 +750,11 @@ enum StaleItem {
         stale: PathBuf,
         stale_mtime: FileTime,
     },
+    ChangedEnv {
+        var: String,
+        previous: Option<String>,
+        current: Option<String>,
+    },
 }
 
 /// Find a stale file in the list of paths comparing to the reference
@@ -711,8 +817,13 @@ pub fn translate_dep_info<'a, I: IntoIterator<Item=&'a Object>>(
     for obj in objects {
         if !idx.contains_key(&obj.src) {
             idx.insert(obj.src.clone(), dep.files.len());
-            let path = obj.src.strip_prefix(pkg_root).unwrap().to_path_buf();
-            dep.files.push((DepInfoPathType::PackageRootRelative, path));
+            // generated sources live in the target directory
+            if let Ok(rel) = obj.src.strip_prefix(pkg_root) {
+                dep.files.push((DepInfoPathType::PackageRootRelative, rel.to_path_buf()));
+            } else {
+                let rel = obj.src.strip_prefix(target_root).unwrap_or(&obj.src);
+                dep.files.push((DepInfoPathType::TargetRootRelative, rel.to_path_buf()));
+            }
         }
 
         let paths = dep_info::read_dependency_file(obj.dep())?;
@@ -750,11 +861,17 @@ pub fn translate_dep_info<'a, I: IntoIterator<Item=&'a Object>>(
 pub struct DepInfo {
     files: Vec<(DepInfoPathType, PathBuf)>,
     objects: Vec<DepObject>,
+    // environment variables read by a step and their values when it ran
+    env: Vec<(String, Option<String>)>,
 }
 
 impl DepInfo {
     pub fn is_empty(&self) -> bool {
-        self.files.is_empty() && self.objects.is_empty()
+        self.files.is_empty() && self.objects.is_empty() && self.env.is_empty()
+    }
+
+    pub fn add_env(&mut self, name: String, value: Option<String>) {
+        self.env.push((name, value));
     }
     
     pub fn add_pkg_relative(&mut self, path: PathBuf) {
@@ -764,6 +881,13 @@ impl DepInfo {
     pub fn add_target_relative(&mut self, path: PathBuf) {
         self.files.push((DepInfoPathType::TargetRootRelative, path));
     }
+
+    /// Absolute paths of all files listed in the dep-info
+    pub fn files(&self, pkg_root: &Path, target_root: &Path) -> Vec<PathBuf> {
+        self.files.iter()
+            .map(|(t, p)| t.path(p, pkg_root, target_root))
+            .collect()
+    }
 }
 
 struct DepObject {
@@ -826,6 +950,19 @@ impl BinaryDeserialize for DepInfo {
             }
             s.objects.push(DepObject { file, inputs })
         }
+        // dep-info written before environment variables were tracked ends here
+        if !r.0.is_empty() {
+            let n_env = r.read_u32()?;
+            for _ in 0..n_env {
+                let name = String::from_utf8(r.read_bytes()?.to_vec()).ok()?;
+                let value = if r.read_u8()? != 0 {
+                    Some(String::from_utf8(r.read_bytes()?.to_vec()).ok()?)
+                } else {
+                    None
+                };
+                s.env.push((name, value));
+            }
+        }
         Some(s)
     }
 }
@@ -844,6 +981,14 @@ impl BinarySerialize for DepInfo {
                 w.write_u32(*inp);
             }
         }
+        w.write_u32(self.env.len() as u32);
+        for (name, value) in self.env.iter() {
+            w.write_bytes(name);
+            w.write_u8(value.is_some() as u8);
+            if let Some(value) = value {
+                w.write_bytes(value);
+            }
+        }
     }
 }
 
@@ -962,6 +1107,10 @@ impl StaleItem {
                 println!("          (vs) {:?}", reference);
                 println!("               {:?} < {:?}", reference_mtime, stale_mtime);
             }
+            StaleItem::ChangedEnv { var, previous, current } => {
+                println!("stale: changed env {:?}", var);
+                println!("       {:?} != {:?}", previous, current);
+            }
         }
     }
 }
@@ -45,6 +45,13 @@ impl Layout {
         path
 
     }
+
+    /// Directory where external projects of a package are built and installed
+    pub fn external_dir(&self, pkg: &PackageId) -> PathBuf {
+        let mut path = self.target_dir.join("external");
+        path.push(pkg.unique_name());
+        path
+    }
 }
 
 
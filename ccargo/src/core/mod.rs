@@ -7,17 +7,22 @@ pub use config::Config;
 pub mod fingerprint;
 pub use fingerprint::{Fingerprint, FingerprintState};
 
+pub mod external;
+pub use external::{External, ExternalKind};
+
 mod layout;
 pub use layout::{Layout, PublicPrivate};
 
 mod package;
-pub use package::{Package, PackageInner, PackageMap, Target, TargetInner, TargetKind, Dependency};
+pub use package::{Package, PackageInner, PackageMap, Target, TargetInner, TargetKind, Dependency, ExportHeader};
+
+pub mod pkg_config;
 
 mod package_id;
 pub use package_id::{PackageId, SourceId};
 
 mod step;
-pub use step::{Step, StepInner, Program};
+pub use step::{Step, StepInner, StepDirectives, Program, split_command, arg_units};
 
 mod target_name;
 pub use target_name::TargetName;
@@ -1,7 +1,8 @@
-use crate::cc::{BinType, Options, Build, Output};
-use crate::core::{TargetName, PackageId, SourceId, Step, Context, FingerprintState, Layout, PublicPrivate, fingerprint};
-use crate::utils::{IResult, InternedString, MsgWriter, paths};
+use crate::cc::{BinType, Crt, Options, Build, Output, Profile, Language, config_checks};
+use crate::core::{TargetName, PackageId, SourceId, Step, StepDirectives, Unit, External, Context, external, FingerprintState, Layout, PublicPrivate, fingerprint};
+use crate::utils::{IResult, InternedString, MsgWriter, hash_u64, paths};
 use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -96,6 +97,7 @@ pub struct PackageInner {
     pub targets: Vec<Target>,
     pub steps: Vec<Step>,
     pub dependencies: Vec<Dependency>,
+    pub profiles: BTreeMap<InternedString, Profile>,
     pub warnings: Vec<String>,
 }
 
@@ -125,6 +127,16 @@ impl Package {
     pub fn root(&self) -> &Path {
         self.id.root()
     }
+
+    /// Profile with the given name (`dev`/`release`) including overrides from the manifest
+    pub fn profile(&self, name: &str) -> Option<Profile> {
+        match self.profiles.get(name) {
+            Some(p) => Some(p.clone()),
+            None if name == "dev" => Some(Profile::dev()),
+            None if name == "release" => Some(Profile::release()),
+            None => None,
+        }
+    }
 }
 
 impl Eq for Package {}
@@ -150,6 +162,17 @@ pub enum TargetKind {
     Test,
     Bench,
     Example,
+    // library installed on the system (e.g. found with pkg-config), not built by ccargo
+    System,
+    // library without sources (e.g. header-only) that only carries its usage requirements to consumers
+    Interface,
+}
+
+impl TargetKind {
+    /// Whether targets of this kind produce an output file that consumers link against
+    pub fn has_output(self) -> bool {
+        !matches!(self, Self::System | Self::Interface)
+    }
 }
 
 
@@ -167,8 +190,67 @@ pub struct TargetInner {
     pub depends: Vec<PublicPrivate<TargetName>>,
     pub includes: Vec<PublicPrivate<PathBuf>>,
     pub defines: Vec<PublicPrivate<(String, Option<String>)>>,
+    pub links: Vec<PublicPrivate<String>>,
+    pub search_paths: Vec<PathBuf>,
     pub rpath: Option<PathBuf>,
-    pub export_header: Option<PathBuf>,
+    pub export_header: Option<ExportHeader>,
+    // static library that may be part of a dependency cycle, linked as a group
+    pub link_group: bool,
+    // static library whose objects are all linked, even if nothing references them
+    pub whole_archive: bool,
+    // dependencies that this target links as a whole archive
+    pub whole_archive_deps: Vec<TargetName>,
+    // version script or symbol list of the symbols exported by a shared library
+    pub exports: Option<PathBuf>,
+    // version in the soname of a shared library
+    pub soversion: Option<String>,
+    // generate a header with the package version that is added as a public include
+    pub version_header: bool,
+    // `git describe` output written by a step that is added to the version header
+    pub git_describe: Option<PathBuf>,
+    // feature probes written to a generated `<target>_config.h` (define name, check)
+    pub config_checks: Vec<(String, config_checks::Check)>,
+    // binary files compiled into the target (file, symbol)
+    pub embeds: Vec<(PathBuf, String)>,
+    // prebuilt library that is linked instead of compiling `sources`
+    pub prebuilt: Option<PathBuf>,
+    // `.pc` files that a system library was read from
+    pub pkg_config_files: Vec<PathBuf>,
+    // project built by another build system that provides the library instead of `sources`
+    pub external: Option<External>,
+}
+
+impl TargetInner {
+    /// Target without sources, dependencies and options,
+    /// the other fields are set with struct update syntax
+    pub fn new(name: InternedString, kind: TargetKind, package: PackageId) -> Self {
+        Self{
+            name,
+            package,
+            kind,
+            sources: Vec::new(),
+            options: Options::default(),
+            depends: Vec::new(),
+            includes: Vec::new(),
+            defines: Vec::new(),
+            links: Vec::new(),
+            search_paths: Vec::new(),
+            rpath: None,
+            export_header: None,
+            link_group: false,
+            whole_archive: false,
+            whole_archive_deps: Vec::new(),
+            exports: None,
+            soversion: None,
+            version_header: false,
+            git_describe: None,
+            config_checks: Vec::new(),
+            embeds: Vec::new(),
+            prebuilt: None,
+            pkg_config_files: Vec::new(),
+            external: None,
+        }
+    }
 }
 
 impl std::ops::Deref for Target {
@@ -194,6 +276,15 @@ impl Target {
         TargetName::new(self.package.name(), self.name)
     }
 
+    /// C++ if any of the sources are C++, otherwise C
+    pub fn language(&self) -> Language {
+        if self.sources.iter().any(|v| Language::detect(v).is_cxx()) {
+            Language::Cxx
+        } else {
+            Language::C
+        }
+    }
+
     pub fn stable_hash<'a>(&self, ws: &'a Path) -> TargetStableHash<'a> {
         TargetStableHash(self.clone(), ws)
     }
@@ -220,27 +311,231 @@ impl std::fmt::Debug for Target {
 }
 
 
+/// Generated header with the macros that export the symbols of a shared library
+#[derive(Debug, Clone)]
+pub struct ExportHeader {
+    pub path: PathBuf,
+    // name the macros are derived from, targets with the same base name can share the header
+    pub base_name: String,
+    // prefix and suffix of the export macro (e.g. `EXPORT_` + `foo` + ``)
+    pub prefix: String,
+    pub suffix: String,
+    // also define `NO_EXPORT` and `DEPRECATED` macros
+    pub extra_macros: bool,
+}
+
 /// Dependency
 #[derive(Debug, Clone)]
 pub struct Dependency {
     pub name: InternedString,
     pub source_id: SourceId,
+    // pkg-config specification for system dependencies (e.g. `zlib >= 1.2`)
+    pub pkg_config: Option<String>,
+    // link the system dependency statically, with the private libraries of its `.pc` file
+    pub static_link: bool,
 }
 
 
 impl Target {
     pub fn output_name(&self, target_triple: &str) -> PathBuf {
-        let mut path = PathBuf::from(&self.name);
-        path.set_extension(BinType::from(self.kind).ext(target_triple));
+        if let Some(prebuilt) = &self.prebuilt {
+            return PathBuf::from(prebuilt.file_name().unwrap());
+        }
+        if let Some(external) = &self.external {
+            let prefix = if target_triple.contains("msvc") { "" } else { "lib" };
+            let ext = BinType::from(self.kind).ext(target_triple);
+            return PathBuf::from(format!("{prefix}{}.{ext}", external.lib));
+        }
+        let bin_type = BinType::from(self.kind);
+        let mut path = PathBuf::from(format!("{}{}", bin_type.prefix(target_triple), self.name));
+        path.set_extension(bin_type.ext(target_triple));
         path
     }
 
     pub fn output_path(&self, layout: &Layout, target_triple: &str) -> PathBuf {
+        if let Some(prebuilt) = &self.prebuilt {
+            return prebuilt.clone();
+        }
+        if let Some(prefix) = self.external_prefix(layout) {
+            return prefix.join("lib").join(self.output_name(target_triple));
+        }
         let mut path = layout.output_dir(&self.package);
         path.push(self.output_name(target_triple));
         path
     }
 
+    /// Directory of the objects compiled from the sources of the target
+    pub fn obj_dir(&self, layout: &Layout) -> PathBuf {
+        layout.output_dir(&self.package).join(format!("{}.dir", self.name))
+    }
+
+    /// Staging directory that the external project of this target is installed into
+    pub fn external_prefix(&self, layout: &Layout) -> Option<PathBuf> {
+        self.external.as_ref()
+            .map(|_| layout.external_dir(&self.package).join(self.name).join("prefix"))
+    }
+
+    /// Directory of the generated version header, added as a public include
+    pub fn version_header_dir(&self, layout: &Layout) -> Option<PathBuf> {
+        self.version_header.then(|| layout.output_dir(&self.package).join("include"))
+    }
+
+    /// Directory of the generated `<target>_config.h` and `<target>_embed.h`, added as a public include
+    pub fn gen_include_dir(&self, layout: &Layout) -> Option<PathBuf> {
+        (!self.config_checks.is_empty() || !self.embeds.is_empty())
+            .then(|| layout.output_dir(&self.package).join("include").join(self.name.as_str()))
+    }
+
+    /// Generated sources that define the embedded files
+    pub fn embed_sources(&self, layout: &Layout, target_triple: &str) -> Vec<PathBuf> {
+        let dir = layout.output_dir(&self.package).join(format!("{}.embed", self.name));
+        self.embeds
+            .iter()
+            .map(|(file, symbol)| {
+                let ext = if use_incbin(file, target_triple) { "S" } else { "c" };
+                dir.join(format!("{symbol}.{ext}"))
+            })
+            .collect()
+    }
+
+    /// Sources of the target and the generated sources of its embedded files
+    pub fn all_sources(&self, layout: &Layout, target_triple: &str) -> Vec<PathBuf> {
+        let mut sources = self.sources.clone();
+        sources.extend(self.embed_sources(layout, target_triple));
+        sources
+    }
+
+    /// Write the generated headers of the target, only touching the files when their contents change
+    pub fn gen_headers(&self, cx: &Context) -> IResult<()> {
+        if let Some(header) = &self.export_header {
+            paths::create_dir_all(header.path.parent().unwrap())?;
+            paths::write_if_changed(&header.path, header.contents())?;
+        }
+        if let Some(dir) = self.version_header_dir(cx.layout) {
+            let git = match &self.git_describe {
+                Some(path) if path.exists() => Some(paths::read_string(path)?.trim().to_string()),
+                _ => None,
+            };
+            let name = c_identifier(&self.package.name());
+            paths::create_dir_all(&dir)?;
+            paths::write_if_changed(
+                dir.join(format!("{}_version.h", name.to_lowercase())),
+                version_header(&name.to_uppercase(), self.package.version(), git.as_deref()),
+            )?;
+        }
+        if let Some(dir) = self.gen_include_dir(cx.layout) {
+            paths::create_dir_all(&dir)?;
+            let name = c_identifier(&self.name);
+            if !self.config_checks.is_empty() {
+                let results = self.run_config_checks(cx)?;
+                paths::write_if_changed(
+                    dir.join(format!("{}_config.h", name.to_lowercase())),
+                    config_checks::config_header(&name, results.iter().map(|(k, v)| (k.as_str(), *v))),
+                )?;
+            }
+            if !self.embeds.is_empty() {
+                paths::write_if_changed(
+                    dir.join(format!("{}_embed.h", name.to_lowercase())),
+                    embed_header(&name, self.embeds.iter().map(|(_, symbol)| symbol.as_str())),
+                )?;
+                self.gen_embed_sources(cx)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Sources are only regenerated when the embedded file is newer or its path or size
+    // changed (recorded in the first line), so large files are not read on every build
+    fn gen_embed_sources(&self, cx: &Context) -> IResult<()> {
+        use std::io::BufRead;
+        let triple = cx.toolchain.target();
+        for ((file, symbol), src) in self.embeds.iter().zip(self.embed_sources(cx.layout, triple)) {
+            let (mtime, len) = (paths::mtime(file)?, file.metadata()?.len());
+            if paths::mtime(&src).is_ok_and(|v| mtime <= v) {
+                let first = std::fs::File::open(&src).ok()
+                    .and_then(|f| std::io::BufReader::new(f).lines().next()?.ok());
+                if first.is_some_and(|v| v == embed_comment(file, len)) {
+                    continue;
+                }
+            }
+            let contents = if src.extension().unwrap() == "S" {
+                embed_asm(file, symbol, len, triple)
+            } else {
+                embed_c(&c_identifier(&self.name), symbol, file, &paths::read_bytes(file)?)
+            };
+            paths::write_create_all(&src, contents)?;
+        }
+        Ok(())
+    }
+
+    // Run the `config` checks with the toolchain and the flags the target is compiled with
+    fn run_config_checks(&self, cx: &Context) -> IResult<Vec<(String, Option<u64>)>> {
+        let deps = &cx.target_deps[self];
+        let lang = self.language();
+        let mut args = Vec::<OsString>::new();
+        for include in deps.includes.iter() {
+            let mut arg = OsString::from("-I");
+            arg.push(include);
+            args.push(arg);
+        }
+        for (k, v) in deps.defines.iter() {
+            args.push(match v {
+                Some(v) => format!("-D{k}={v}"),
+                None => format!("-D{k}"),
+            }.into());
+        }
+        args.extend(self.options.cc_flags.iter().chain(deps.cc_flags.iter()).map(OsString::from));
+        let tools = match cx.toolchain.tools_for(lang) {
+            Some(v) => v,
+            None => anyhow::bail!(
+                "Target `{}` has `config` checks, but the toolchain has no {lang:?} compiler", 
+                self.full_name()
+            ),
+        };
+        let probe = config_checks::Probe{
+            tool: &tools.cc,
+            cpp: lang.is_cxx(),
+            args,
+            links: deps.links.clone(),
+            toolchain: hash_u64(cx.toolchain),
+        };
+        self.config_checks
+            .iter()
+            .map(|(define, check)| Ok((define.clone(), config_checks::run(&probe, check, cx.config)?)))
+            .collect()
+    }
+
+    /// Defines, includes and link settings emitted by the steps the target depends on,
+    /// link settings of steps that linked static libraries depend on are forwarded like their `links`
+    pub fn step_directives(&self, cx: &Context) -> IResult<StepDirectives> {
+        let steps = |target: &Target| {
+            target.depends.iter()
+                .filter_map(|v| cx.units.get(v, &target.package).as_step())
+                .map(|v| v.directives(cx.layout))
+                .collect::<IResult<Vec<_>>>()
+        };
+        let mut directives = StepDirectives::default();
+        for v in steps(self)? {
+            directives.extend(v);
+        }
+        for lib in cx.target_deps[self].libs.iter() {
+            if let Some(Unit::Target(dep)) = cx.units.with_output(lib) {
+                if dep.kind == TargetKind::Static {
+                    for v in steps(dep)? {
+                        directives.links.extend(v.links);
+                        directives.search_paths.extend(v.search_paths);
+                    }
+                }
+            }
+        }
+        Ok(directives)
+    }
+
+    /// Whether the library is built outside of ccargo and linked from where it is
+    pub fn is_foreign(&self) -> bool {
+        self.prebuilt.is_some() || self.external.is_some()
+    }
+
     pub fn dep_info_path(&self, layout: &Layout) -> PathBuf {
         let mut path = layout.fingerprint();
         path.push(&self.package.unique_name());
@@ -275,13 +570,6 @@ impl Target {
         stdout: MsgWriter<O>,
         stderr: MsgWriter<E>,
     ) -> IResult<Output> {
-        // TODO: Better tracking of export header dirtinesss/generation
-        if let Some(path) = &self.export_header {
-            // if !path.exists() {
-                gen_export_header(&self.name, path)?;
-            // }
-        }
-        
         let src_dir = self.package.root();
         let out_dir = cx.layout.output_dir(&self.package);
         let deps = cx.target_deps.get(self).unwrap();
@@ -290,18 +578,15 @@ impl Target {
         //   On Windows, the executable is linked to the `.lib` file statically (which can be anywhere), 
         //   and the `.dll` just needs to be in the executable folder at runtime.
         //
-        //   On Unix, the executable is linked to the `.so`/`.dylib` file relative to the exe path.
-        //   Since libraries are compiled into a different folder than the executable, we need to
-        //   manually tell the linker where the dynamic libraries will be at runtime (i.e. the exe folder, `.`).
-        let libs = if cx.toolchain.target().contains("msvc") {
-            deps.libs.clone()
-        } else {
-            deps.libs.iter().map(|x| x.file_stem().unwrap().into()).collect()
-        };
+        //   On Unix, libraries are linked by path and executables record their soname/install name,
+        //   which is found relative to the exe path at runtime (i.e. the exe folder, `.`).
 
         // TODO: Add support for key-value defines at for TOML and for CC::BUILD
+        let directives = self.step_directives(cx)?;
         let mut options = self.options.clone();
-        for (k, v) in deps.defines.iter() {
+        options.cc_flags.extend(deps.cc_flags.iter().cloned());
+        options.ld_flags.extend(deps.ld_flags.iter().cloned());
+        for (k, v) in deps.defines.iter().chain(directives.defines.iter().map(|(k, v)| (k, v))) {
             if let Some(v) = v {
                 options.defines.insert(format!("{k}={v}"));
             } else {
@@ -309,25 +594,75 @@ impl Target {
             }
         }
         
+        // the C runtime is linked the same way as rust static libraries link it
+        if options.crt == Crt::Default && external::links_shared_crt(cx, self) {
+            options.crt = Crt::Shared;
+        }
+
+        // rust static libraries need the native libraries of the rust standard library
+        let mut links = deps.links.clone();
+        for link in directives.links {
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+        for lib in deps.libs.iter() {
+            if let Some(target) = cx.units.with_output(lib).and_then(Unit::as_target) {
+                for link in external::native_libs(cx, target)? {
+                    // flags are a set, so the framework is passed as a single argument
+                    if let Some(framework) = link.strip_prefix("framework=") {
+                        options.ld_flags.insert(format!("-Wl,-framework,{framework}"));
+                    } else if !links.contains(&link) {
+                        links.push(link);
+                    }
+                }
+            }
+        }
+
+        let sources = self.all_sources(cx.layout, cx.toolchain.target());
         let mut b = Build::new(&self.name, self.kind.into(), cx.toolchain.clone());
+        for group in deps.link_groups.iter() {
+            b.link_group(group);
+        }
+        b.whole_archive(&deps.whole_archive);
+        if let Some(path) = &self.exports {
+            b.exports(path);
+        }
+        if self.kind == TargetKind::Shared {
+            b.version(&self.package.version().to_string());
+            if let Some(soversion) = &self.soversion {
+                b.soversion(soversion);
+            }
+        }
         
         if state.files.is_empty() {
             b.skip_compile();
         } else {
-            for path in self.sources.iter() {
+            for path in sources.iter() {
                 if !state.files.contains(path) {
                     b.skip_file(path);
                 }
             }
         }
 
+        // Remove machine-specific paths from the outputs, 
+        // the most specific paths are mapped last so they take precedence
+        if cx.profile.reproducible {
+            b.remap_path_prefix(src_dir, format!("{}-{}", self.package.name(), self.package.version()))
+                .remap_path_prefix(cx.config.home(), "ccargo-home")
+                .remap_path_prefix(cx.layout.target(), "target");
+        }
+
         let output = b
             .src_dir(src_dir)
             .out_dir(&out_dir)
+            .obj_dir(self.obj_dir(cx.layout))
             .options(options)
-            .files(self.sources.iter().cloned())
-            .includes(&deps.includes)
-            .libraries(libs)
+            .files(sources)
+            .includes(deps.includes.iter().chain(directives.includes.iter()))
+            .libraries(&deps.libs)
+            .system_libraries(&links)
+            .search_paths(deps.search_paths.iter().chain(directives.search_paths.iter()))
             .profile(cx.profile.clone())
             .stdout(stdout)
             .stderr(stderr)
@@ -351,7 +686,8 @@ impl From<TargetKind> for BinType {
         use TargetKind::*;
         match value {
             Bin | Test | Bench | Example => Self::Exe,
-            Static => Self::Static,
+            // system and interface libraries are never built
+            Static | System | Interface => Self::Static,
             Shared => Self::Shared,
         }
     }
@@ -367,57 +703,305 @@ impl<'a> std::hash::Hash for TargetStableHash<'a> {
         self.0.options.hash(state);
         self.0.depends.hash(state);
         self.0.defines.hash(state);
+        self.0.links.hash(state);
         self.0.rpath.hash(state);
         self.0.package.stable_hash(self.1).hash(state);
-        self.0.export_header
-            .as_ref()
-            .map(|v| v.strip_prefix(self.1).unwrap_or(v))
-            .hash(state);
+        if let Some(header) = &self.0.export_header {
+            header.path.strip_prefix(self.1).unwrap_or(&header.path).hash(state);
+            header.base_name.hash(state);
+            header.prefix.hash(state);
+            header.suffix.hash(state);
+            header.extra_macros.hash(state);
+        }
         for v in self.0.sources.iter() {
             v.strip_prefix(self.1).unwrap().hash(state);
         }
         for v in self.0.includes.iter() {
             v.strip_prefix(self.1).unwrap_or(v).hash(state);
         }
+        for v in self.0.search_paths.iter() {
+            v.strip_prefix(self.1).unwrap_or(v).hash(state);
+        }
+        self.0.exports
+            .as_ref()
+            .map(|v| v.strip_prefix(self.1).unwrap_or(v))
+            .hash(state);
+        self.0.soversion.hash(state);
+        self.0.version_header.hash(state);
+        self.0.git_describe
+            .as_ref()
+            .map(|v| v.strip_prefix(self.1).unwrap_or(v))
+            .hash(state);
+        for (define, check) in self.0.config_checks.iter() {
+            define.hash(state);
+            match check {
+                config_checks::Check::Compiles(v) => v.strip_prefix(self.1).unwrap_or(v).hash(state),
+                _ => check.hash(state),
+            }
+        }
+        for (file, symbol) in self.0.embeds.iter() {
+            file.strip_prefix(self.1).unwrap_or(file).hash(state);
+            symbol.hash(state);
+        }
+        self.0.prebuilt
+            .as_ref()
+            .map(|v| v.strip_prefix(self.1).unwrap_or(v))
+            .hash(state);
+        self.0.pkg_config_files.hash(state);
+        if let Some(external) = &self.0.external {
+            external.kind.hash(state);
+            external.lib.hash(state);
+            external.args.hash(state);
+            external.path.strip_prefix(self.1).unwrap_or(&external.path).hash(state);
+        }
+    }
+}
+
+
+fn version_header(name: &str, version: &semver::Version, git: Option<&str>) -> String {
+    let mut s = format!(r#"#ifndef {name}_VERSION_H
+#define {name}_VERSION_H
+
+#define {name}_VERSION_MAJOR {}
+#define {name}_VERSION_MINOR {}
+#define {name}_VERSION_PATCH {}
+#define {name}_VERSION_STRING "{version}"
+"#, version.major, version.minor, version.patch);
+    if let Some(git) = git {
+        s.push_str(&format!("#define {name}_VERSION_GIT \"{}\"\n", c_escape(git)));
+    }
+    s.push_str("\n#endif\n");
+    s
+}
+
+
+// Escape a string for a C string literal, other bytes than printable ASCII are written
+// as octal escapes since hex escapes would swallow the characters that follow them
+fn c_escape(s: &str) -> String {
+    use std::fmt::Write;
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'\\' | b'"' => { out.push('\\'); out.push(b as char); }
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            // `??x` would be a trigraph
+            b'?' => out.push_str("\\?"),
+            0x20..=0x7e => out.push(b as char),
+            _ => { let _ = write!(out, "\\{b:03o}"); }
+        }
+    }
+    out
+}
+
+
+// Files larger than this are included with `.incbin` instead of being written out as a C array
+const EMBED_INCBIN_SIZE: u64 = 1 << 20;
+
+// MSVC has no assembler that understands `.incbin`, so it always compiles the C array
+fn use_incbin(file: &Path, target_triple: &str) -> bool {
+    !target_triple.contains("msvc") && file.metadata().is_ok_and(|m| m.len() > EMBED_INCBIN_SIZE)
+}
+
+// Name of a target or package with the characters that are not valid in C identifiers replaced
+fn c_identifier(name: &str) -> String {
+    name.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+// Dependencies include the headers of several targets, so the name and guard are per target
+fn embed_header<'a, I: IntoIterator<Item = &'a str>>(name: &str, symbols: I) -> String {
+    let guard = name.to_uppercase();
+    let mut s = format!(r#"/* Generated by ccargo from the `embed` files of the target */
+#ifndef {guard}_EMBED_H
+#define {guard}_EMBED_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {{
+#endif
+
+"#);
+    for symbol in symbols {
+        s.push_str(&format!("extern const unsigned char {symbol}[];\nextern const size_t {symbol}_len;\n"));
     }
+    s.push_str(r#"
+#ifdef __cplusplus
+}
+#endif
+
+#endif
+"#);
+    s
 }
 
+// First line of the generated sources of an embedded file
+fn embed_comment(file: &Path, len: u64) -> String {
+    format!("/* Generated by ccargo from `{}` ({len} bytes) */", file.display())
+}
+
+fn embed_c(name: &str, symbol: &str, file: &Path, bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    let mut s = String::with_capacity(bytes.len() * 5 + 128);
+    let _ = writeln!(s, "{}", embed_comment(file, bytes.len() as u64));
+    let _ = writeln!(s, "#include \"{}_embed.h\"\n", name.to_lowercase());
+    // C does not allow empty arrays
+    let _ = writeln!(s, "const unsigned char {symbol}[] = {{{}", if bytes.is_empty() { "0" } else { "" });
+    for line in bytes.chunks(16) {
+        s.push_str("   ");
+        for b in line {
+            let _ = write!(s, " 0x{b:02x},");
+        }
+        s.push('\n');
+    }
+    let _ = writeln!(s, "}};\nconst size_t {symbol}_len = {};", bytes.len());
+    s
+}
 
-fn gen_export_header(name: &str, path: &Path) -> IResult<()> {
-    paths::create_dir_all(path.parent().unwrap())?;
-    paths::write(path, export_header(
-        name,
-        ("EXPORT_", ""),
-        ("", "_SHARED"),
-        ("", "_EXPORTS"),
-    ))?;
-    Ok(())
+fn embed_asm(file: &Path, symbol: &str, len: u64, target_triple: &str) -> String {
+    let apple = target_triple.contains("apple");
+    let windows = target_triple.contains("windows");
+    let (section, prefix) = if apple {
+        (".const_data", "_")
+    } else if windows {
+        (".section .rdata,\"dr\"", if target_triple.starts_with("i686") { "_" } else { "" })
+    } else {
+        (".section .rodata", "")
+    };
+    let arch = target_triple.split('-').next().unwrap_or_default();
+    let size = if arch.contains("64") || arch == "s390x" { ".quad" } else { ".long" };
+    // `.incbin` takes a string literal, so quotes and backslashes (windows paths) are escaped
+    let path = file.display().to_string().replace('\\', "\\\\").replace('"', "\\\"");
+    let mut s = format!(r#"{}
+    {section}
+    .globl {prefix}{symbol}
+    .balign 16
+{prefix}{symbol}:
+    .incbin "{path}"
+    .globl {prefix}{symbol}_len
+    .balign 8
+{prefix}{symbol}_len:
+    {size} {len}
+"#, embed_comment(file, len));
+    if !apple && !windows {
+        s.push_str("    .section .note.GNU-stack,\"\",%progbits\n");
+    }
+    s
 }
 
 
-fn export_header(
-    name: &str, 
-    api: (&str, &str),
-    shared: (&str, &str), 
-    export: (&str, &str), 
-) -> String {
-    let (prefix, suffix) = api;
-    let (shared_prefix, shared_suffix) = shared;
-    let (export_prefix, export_suffix) = export;
-    format!(r#"#ifdef {shared_prefix}{name}{shared_suffix}
+impl ExportHeader {
+    // Macros are named after the base name like CMake's `GenerateExportHeader`:
+    // `<base>_SHARED` is defined when building or using the shared library, `<base>_EXPORTS` when 
+    // building it and `<base>_STATIC` by static builds that share the header with the shared build
+    fn contents(&self) -> String {
+        let (base, api) = (&self.base_name, self.export_macro());
+        let mut s = format!(r#"#if defined({base}_SHARED) && !defined({base}_STATIC)
     #if defined(_MSC_VER)
-        #ifdef {export_prefix}{name}{export_suffix}
-            #define {prefix}{name}{suffix} __declspec(dllexport)
+        #ifdef {base}_EXPORTS
+            #define {api} __declspec(dllexport)
         #else
-            #define {prefix}{name}{suffix} __declspec(dllimport)
+            #define {api} __declspec(dllimport)
         #endif
     #elif defined(__GNUC__) || defined(__clang__)
-        #define {prefix}{name}{suffix} __attribute__((visibility("default")))
+        #define {api} __attribute__((visibility("default")))
     #else
-        #define {prefix}{name}{suffix}
+        #define {api}
     #endif
 #else
-    #define {prefix}{name}{suffix}
+    #define {api}
+#endif
+"#);
+        if self.extra_macros {
+            s.push_str(&format!(r#"
+#if (defined(__GNUC__) || defined(__clang__)) && !defined(_WIN32)
+    #define {base}_NO_EXPORT __attribute__((visibility("hidden")))
+#else
+    #define {base}_NO_EXPORT
 #endif
-"#)
+
+#if defined(_MSC_VER)
+    #define {base}_DEPRECATED __declspec(deprecated)
+#elif defined(__GNUC__) || defined(__clang__)
+    #define {base}_DEPRECATED __attribute__((__deprecated__))
+#else
+    #define {base}_DEPRECATED
+#endif
+
+#define {base}_DEPRECATED_EXPORT {api} {base}_DEPRECATED
+#define {base}_DEPRECATED_NO_EXPORT {base}_NO_EXPORT {base}_DEPRECATED
+"#));
+        }
+        s
+    }
+
+    /// Name of the macro that exports a symbol from the shared library
+    pub fn export_macro(&self) -> String {
+        format!("{}{}{}", self.prefix, self.base_name, self.suffix)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embed_sources() {
+        let header = embed_header("my_lib", ["logo_png", "font"]);
+        assert!(header.starts_with("/* Generated by ccargo from the `embed` files of the target */\n#ifndef MY_LIB_EMBED_H\n#define MY_LIB_EMBED_H\n"));
+        assert!(header.contains("extern const unsigned char logo_png[];\nextern const size_t logo_png_len;\nextern const unsigned char font[];\n"));
+
+        assert_eq!(
+            embed_c("my_lib", "data", Path::new("data.bin"), &(0..18).collect::<Vec<u8>>()),
+            "/* Generated by ccargo from `data.bin` (18 bytes) */\n\
+             #include \"my_lib_embed.h\"\n\n\
+             const unsigned char data[] = {\n    \
+             0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,\n    \
+             0x10, 0x11,\n\
+             };\nconst size_t data_len = 18;\n"
+        );
+        assert_eq!(embed_c("a", "empty", Path::new("empty"), &[]), "/* Generated by ccargo from `empty` (0 bytes) */\n#include \"a_embed.h\"\n\nconst unsigned char empty[] = {0\n};\nconst size_t empty_len = 0;\n");
+
+        let asm = embed_asm(Path::new(r#"C:\data\"big".bin"#), "big", 42, "x86_64-pc-windows-gnu");
+        assert!(asm.contains("    .section .rdata,\"dr\"\n    .globl big\n"));
+        assert!(asm.contains(r#"    .incbin "C:\\data\\\"big\".bin""#));
+        assert!(asm.contains("big_len:\n    .quad 42\n"));
+        let asm = embed_asm(Path::new("/data/big.bin"), "big", 42, "i686-unknown-linux-gnu");
+        assert!(asm.starts_with("/* Generated by ccargo from `/data/big.bin` (42 bytes) */\n    .section .rodata\n"));
+        assert!(asm.contains("    .section .rodata\n    .globl big\n"));
+        assert!(asm.contains("    .long 42\n    .section .note.GNU-stack"));
+        assert!(embed_asm(Path::new("/data/big.bin"), "big", 42, "x86_64-apple-darwin").contains(".globl _big\n"));
+    }
+
+    #[test]
+    fn version_header_() {
+        let version = semver::Version::parse("1.2.3-beta").unwrap();
+        assert_eq!(version_header("MY_LIB", &version, None), r#"#ifndef MY_LIB_VERSION_H
+#define MY_LIB_VERSION_H
+
+#define MY_LIB_VERSION_MAJOR 1
+#define MY_LIB_VERSION_MINOR 2
+#define MY_LIB_VERSION_PATCH 3
+#define MY_LIB_VERSION_STRING "1.2.3-beta"
+
+#endif
+"#);
+        let header = version_header("A", &version, Some("v1.2-3-gabc\"é\\??"));
+        assert!(header.contains("#define A_VERSION_GIT \"v1.2-3-gabc\\\"\\303\\251\\\\\\?\\?\"\n\n#endif\n"));
+        assert_eq!(c_escape("a\tb\n\x7f"), "a\\tb\\n\\177");
+    }
+
+    #[test]
+    fn embed_incbin_for_large_files() {
+        let dir = std::env::temp_dir().join("ccargo-test-embed-incbin");
+        paths::create_dir_all(&dir).unwrap();
+        let (small, large) = (dir.join("small.bin"), dir.join("large.bin"));
+        paths::write(&small, [0u8; 16]).unwrap();
+        paths::write(&large, vec![0u8; EMBED_INCBIN_SIZE as usize + 1]).unwrap();
+        assert!(!use_incbin(&small, "x86_64-unknown-linux-gnu"));
+        assert!(use_incbin(&large, "x86_64-unknown-linux-gnu"));
+        assert!(!use_incbin(&large, "x86_64-pc-windows-msvc"));
+        drop(std::fs::remove_dir_all(&dir));
+    }
 }
@@ -0,0 +1,474 @@
+use crate::cc::host_triple;
+use crate::core::{Dependency, Package, PackageInner, PackageId, PublicPrivate, Target, TargetInner, TargetKind};
+use crate::utils::{IResult, paths};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context as _};
+
+
+/// Find the `.pc` file for a pkg-config dependency specification (e.g. `zlib >= 1.2`)
+pub fn find(spec: &str) -> IResult<PathBuf> {
+    let req = parse_requires(spec)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("empty pkg-config specification"))?;
+    find_pc_file(&req.name)
+}
+
+
+/// Create a package with a single system library target from a pkg-config dependency
+pub fn load_package(dep: &Dependency) -> IResult<Package> {
+    let spec = dep.pkg_config.as_deref().unwrap();
+    let path = dep.source_id.path();
+    let mut warnings = Vec::new();
+
+    let req = parse_requires(spec)?.into_iter().next().unwrap();
+    let pc = PcFile::read(path)?;
+    req.check(&pc)?;
+
+    let id = PackageId::new(dep.name, semver_lossy(&pc.version), dep.source_id)?;
+
+    // Flags of required packages are merged into the system library target
+    let mut cflags = Vec::new();
+    let mut libs = Vec::new();
+    let mut seen = HashMap::new();
+    collect_flags(&pc, dep.static_link, &mut cflags, &mut libs, &mut seen)?;
+    // Every `.pc` file that was read is an input of the target
+    let mut pkg_config_files = seen.into_keys().collect::<Vec<_>>();
+    pkg_config_files.sort();
+
+    let mut includes = Vec::new();
+    let mut defines = Vec::new();
+    let mut links = Vec::new();
+    let mut search_paths = Vec::new();
+    let mut flags = cflags.iter().chain(libs.iter()).peekable();
+    while let Some(flag) = flags.next() {
+        // flags like `-I` can be separated from their value
+        let mut value = |prefix: &str| -> Option<String> {
+            let v = flag.strip_prefix(prefix)?;
+            if v.is_empty() { flags.next().cloned() } else { Some(v.to_string()) }
+        };
+        if let Some(v) = value("-I") {
+            includes.push(PublicPrivate::public(PathBuf::from(v)));
+        } else if let Some(v) = value("-D") {
+            let (k, v) = match v.split_once('=') {
+                Some((k, v)) => (k.to_string(), Some(v.to_string())),
+                None => (v, None),
+            };
+            defines.push(PublicPrivate::public((k, v)));
+        } else if let Some(v) = value("-L") {
+            search_paths.push(PathBuf::from(v));
+        } else if let Some(v) = value("-l") {
+            links.push(PublicPrivate::public(v));
+        } else if flag == "-pthread" {
+            links.push(PublicPrivate::public("pthread".to_string()));
+        } else {
+            warnings.push(format!(
+                "unsupported flag `{flag}` in pkg-config file `{}` was ignored",
+                path.display()
+            ));
+        }
+    }
+
+    // Remove duplicates while preserving the order (last occurrence is kept for links)
+    let mut seen = HashSet::new();
+    includes.retain(|v| seen.insert(v.to_path_buf()));
+    let mut seen = HashSet::new();
+    search_paths.retain(|v| seen.insert(v.clone()));
+    let mut seen = HashSet::new();
+    links.reverse();
+    links.retain(|v| seen.insert(v.to_string()));
+    links.reverse();
+
+    let target = Target::new(TargetInner{
+        includes,
+        defines,
+        links,
+        search_paths,
+        pkg_config_files,
+        ..TargetInner::new(dep.name, TargetKind::System, id)
+    });
+
+    Ok(Package::new(PackageInner{
+        id,
+        targets: vec![target],
+        steps: Vec::new(),
+        dependencies: Vec::new(),
+        profiles: BTreeMap::new(),
+        warnings,
+    }))
+}
+
+
+// Parsed pkg-config `.pc` file
+#[derive(Debug, Default)]
+struct PcFile {
+    path: PathBuf,
+    version: String,
+    cflags: Vec<String>,
+    libs: Vec<String>,
+    libs_private: Vec<String>,
+    requires: Vec<Requirement>,
+    requires_private: Vec<Requirement>,
+}
+
+impl PcFile {
+    fn read(path: &Path) -> IResult<Self> {
+        let contents = paths::read_string(path)?;
+        Self::parse(path, &contents)
+            .with_context(|| format!("failed to parse pkg-config file `{}`", path.display()))
+    }
+
+    fn parse(path: &Path, contents: &str) -> IResult<Self> {
+        let mut pc = Self{path: path.to_path_buf(), ..Self::default()};
+        let mut vars = BTreeMap::new();
+        let dir = path.parent().unwrap_or(Path::new("."));
+        vars.insert("pcfiledir".to_string(), dir.display().to_string());
+
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            // `var=value` defines a variable, `Key: value` defines a field
+            let var = line.find('=');
+            let field = line.find(':');
+            match (var, field) {
+                (Some(i), f) if f.is_none_or(|f| i < f) => {
+                    let value = expand(line[i+1..].trim(), &vars)?;
+                    vars.insert(line[..i].trim().to_string(), value);
+                }
+                (_, Some(i)) => {
+                    let value = expand(line[i+1..].trim(), &vars)?;
+                    match line[..i].trim() {
+                        "Version" => pc.version = value,
+                        "Cflags" | "CFlags" => pc.cflags = split_flags(&value),
+                        "Libs" => pc.libs = split_flags(&value),
+                        "Libs.private" => pc.libs_private = split_flags(&value),
+                        "Requires" => pc.requires = parse_requires(&value)?,
+                        "Requires.private" => pc.requires_private = parse_requires(&value)?,
+                        _ => {}
+                    }
+                }
+                _ => bail!("invalid line `{line}`"),
+            }
+        }
+        Ok(pc)
+    }
+}
+
+
+// Requirement on another package (e.g. `zlib >= 1.2`)
+#[derive(Debug, Clone, PartialEq)]
+struct Requirement {
+    name: String,
+    version: Option<(String, String)>,
+}
+
+impl Requirement {
+    fn check(&self, pc: &PcFile) -> IResult<()> {
+        let (op, version) = match &self.version {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let ord = compare_versions(&pc.version, version);
+        let ok = match op.as_str() {
+            "=" => ord == Ordering::Equal,
+            "!=" => ord != Ordering::Equal,
+            "<" => ord == Ordering::Less,
+            "<=" => ord != Ordering::Greater,
+            ">" => ord == Ordering::Greater,
+            ">=" => ord != Ordering::Less,
+            _ => bail!("unknown version operator `{op}`"),
+        };
+        if !ok {
+            bail!(
+                "pkg-config package `{}` has version {}, but `{} {op} {version}` is required",
+                self.name, pc.version, self.name
+            )
+        }
+        Ok(())
+    }
+}
+
+
+// Recursively collect the flags of a package and the packages it requires,
+// `static_link` adds the private libraries and requirements like `pkg-config --static`.
+// `seen` maps the `.pc` files read to whether their libraries were collected (not only cflags)
+fn collect_flags(
+    pc: &PcFile,
+    static_link: bool,
+    cflags: &mut Vec<String>,
+    libs: &mut Vec<String>,
+    seen: &mut HashMap<PathBuf, bool>,
+) -> IResult<()> {
+    match seen.insert(pc.path.clone(), true) {
+        Some(true) => return Ok(()),
+        // only the cflags were collected for a private requirement
+        Some(false) => {}
+        None => cflags.extend(pc.cflags.iter().cloned()),
+    }
+    libs.extend(pc.libs.iter().cloned());
+    if static_link {
+        libs.extend(pc.libs_private.iter().cloned());
+    }
+    for req in pc.requires.iter() {
+        let dep = PcFile::read(&find_pc_file(&req.name)?)?;
+        req.check(&dep)?;
+        collect_flags(&dep, static_link, cflags, libs, seen)?;
+    }
+    // Private requirements are only needed for compilation when linking dynamically
+    for req in pc.requires_private.iter() {
+        let dep = PcFile::read(&find_pc_file(&req.name)?)?;
+        req.check(&dep)?;
+        if static_link {
+            collect_flags(&dep, static_link, cflags, libs, seen)?;
+        } else if !seen.contains_key(&dep.path) {
+            seen.insert(dep.path.clone(), false);
+            cflags.extend(dep.cflags.iter().cloned());
+        }
+    }
+    Ok(())
+}
+
+
+// Search `PKG_CONFIG_PATH` and the default directories for `<name>.pc`
+fn find_pc_file(name: &str) -> IResult<PathBuf> {
+    let file = format!("{name}.pc");
+    let dirs = search_dirs();
+    dirs.iter()
+        .map(|d| d.join(&file))
+        .find(|p| p.exists())
+        .ok_or_else(|| anyhow::anyhow!(
+            "could not find pkg-config file `{file}`, searched:\n{}",
+            dirs.iter().map(|d| format!("    {}", d.display())).collect::<Vec<_>>().join("\n")
+        ))
+}
+
+
+// Directories searched for `.pc` files
+fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(path) = std::env::var_os("PKG_CONFIG_PATH") {
+        dirs.extend(std::env::split_paths(&path));
+    }
+    // `PKG_CONFIG_LIBDIR` replaces the default search path
+    if let Some(path) = std::env::var_os("PKG_CONFIG_LIBDIR") {
+        dirs.extend(std::env::split_paths(&path));
+        return dirs;
+    }
+    let host = host_triple();
+    let arch = host.split('-').next().unwrap();
+    for dir in [
+        "/usr/local/lib/pkgconfig".to_string(),
+        "/usr/local/share/pkgconfig".to_string(),
+        format!("/usr/lib/{arch}-linux-gnu/pkgconfig"),
+        "/usr/lib64/pkgconfig".to_string(),
+        "/usr/lib/pkgconfig".to_string(),
+        "/usr/share/pkgconfig".to_string(),
+        "/opt/homebrew/lib/pkgconfig".to_string(),
+    ] {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs
+}
+
+
+// Expand `${var}` references in a value, `$$` is a literal `$`
+fn expand(value: &str, vars: &BTreeMap<String, String>) -> IResult<String> {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i+1..];
+        if let Some(r) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('{') {
+            let end = r.find('}').ok_or_else(|| anyhow::anyhow!("unterminated variable in `{value}`"))?;
+            let name = &r[..end];
+            match vars.get(name) {
+                Some(v) => out.push_str(v),
+                None => bail!("undefined variable `{name}` in `{value}`"),
+            }
+            rest = &r[end+1..];
+        } else {
+            out.push('$');
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+
+// Split a list of flags on whitespace, respecting quotes and backslash escapes
+fn split_flags(value: &str) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut cur = String::new();
+    let mut quote = None;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => cur.extend(chars.next()),
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if !cur.is_empty() {
+                    flags.push(std::mem::take(&mut cur));
+                }
+            }
+            (c, _) => cur.push(c),
+        }
+    }
+    if !cur.is_empty() {
+        flags.push(cur);
+    }
+    flags
+}
+
+
+// Parse a list of requirements like `glib-2.0 >= 2.50, zlib`
+fn parse_requires(value: &str) -> IResult<Vec<Requirement>> {
+    let mut reqs: Vec<Requirement> = Vec::new();
+    let mut tokens = value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .peekable();
+    while let Some(name) = tokens.next() {
+        let mut req = Requirement{name: name.to_string(), version: None};
+        if let Some(&op) = tokens.peek() {
+            if matches!(op, "=" | "!=" | "<" | "<=" | ">" | ">=") {
+                tokens.next();
+                let version = tokens.next()
+                    .ok_or_else(|| anyhow::anyhow!("missing version after `{name} {op}`"))?;
+                req.version = Some((op.to_string(), version.to_string()));
+            }
+        }
+        reqs.push(req);
+    }
+    Ok(reqs)
+}
+
+
+// Compare versions segment by segment, numeric segments are compared as numbers
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn segments(v: &str) -> Vec<&str> {
+        let mut segs = Vec::new();
+        let mut start = None;
+        let bytes = v.as_bytes();
+        for i in 0..=bytes.len() {
+            let kind = bytes.get(i).map(|c| {
+                if c.is_ascii_digit() { 1 } else if c.is_ascii_alphabetic() { 2 } else { 0 }
+            });
+            if let Some((s, k)) = start {
+                if kind != Some(k) {
+                    segs.push(&v[s..i]);
+                    start = None;
+                }
+            }
+            if start.is_none() {
+                if let Some(k) = kind.filter(|k| *k != 0) {
+                    start = Some((i, k));
+                }
+            }
+        }
+        segs
+    }
+    let (a, b) = (segments(a), segments(b));
+    for (x, y) in a.iter().zip(b.iter()) {
+        let ord = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            // numeric segments are newer than alphabetic ones
+            (Ok(_), Err(_)) => Ordering::Greater,
+            (Err(_), Ok(_)) => Ordering::Less,
+            (Err(_), Err(_)) => x.cmp(y),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+
+// Convert a pkg-config version to semver (e.g. `1.2.11.1` -> `1.2.11`, `3.0` -> `3.0.0`)
+fn semver_lossy(version: &str) -> semver::Version {
+    let mut parts = version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<u64>().unwrap_or(0));
+    semver::Version::new(
+        parts.next().unwrap_or(0),
+        parts.next().unwrap_or(0),
+        parts.next().unwrap_or(0),
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pc_file() {
+        let pc = PcFile::parse(Path::new("/usr/lib/pkgconfig/foo.pc"), r#"
+prefix=/usr
+libdir=${prefix}/lib
+includedir=${prefix}/include # comment
+
+Name: foo
+Version: 1.2.11
+Requires: bar >= 2.0, baz
+Libs: -L${libdir} -lfoo
+Libs.private: -lz -lm
+Cflags: -I${includedir}/foo -DFOO="a b" -I${pcfiledir}
+"#).unwrap();
+        assert_eq!(pc.version, "1.2.11");
+        assert_eq!(pc.libs, ["-L/usr/lib", "-lfoo"]);
+        assert_eq!(pc.libs_private, ["-lz", "-lm"]);
+        assert_eq!(pc.cflags, ["-I/usr/include/foo", "-DFOO=a b", "-I/usr/lib/pkgconfig"]);
+        assert_eq!(pc.requires, [
+            Requirement{name: "bar".into(), version: Some((">=".into(), "2.0".into()))},
+            Requirement{name: "baz".into(), version: None},
+        ]);
+
+        // private libraries are only linked statically
+        let pc = PcFile{requires: Vec::new(), ..pc};
+        for (static_link, expected) in [(false, &["-L/usr/lib", "-lfoo"][..]), (true, &["-L/usr/lib", "-lfoo", "-lz", "-lm"])] {
+            let (mut cflags, mut libs, mut seen) = (Vec::new(), Vec::new(), HashMap::new());
+            collect_flags(&pc, static_link, &mut cflags, &mut libs, &mut seen).unwrap();
+            assert_eq!(libs, expected);
+            assert_eq!(seen, HashMap::from([(PathBuf::from("/usr/lib/pkgconfig/foo.pc"), true)]));
+        }
+    }
+
+    #[test]
+    fn private_then_public_requirement() {
+        let dir = std::env::temp_dir().join("ccargo-test-pkg-config");
+        paths::create_dir_all(&dir).unwrap();
+        // `a` requires `c` (which privately requires `b`) and `d` (which requires `b`)
+        for (name, requires) in [("a", "Requires: ccargo-c, ccargo-d"), ("b", ""), ("c", "Requires.private: ccargo-b"), ("d", "Requires: ccargo-b")] {
+            let pc = format!("Name: {name}\nVersion: 1.0\n{requires}\nLibs: -l{name}\nCflags: -I/{name}\n");
+            paths::write(dir.join(format!("ccargo-{name}.pc")), pc).unwrap();
+        }
+        std::env::set_var("PKG_CONFIG_PATH", &dir);
+        let pc = PcFile::read(&dir.join("ccargo-a.pc")).unwrap();
+        let (mut cflags, mut libs, mut seen) = (Vec::new(), Vec::new(), HashMap::new());
+        collect_flags(&pc, false, &mut cflags, &mut libs, &mut seen).unwrap();
+        assert_eq!(cflags, ["-I/a", "-I/c", "-I/b", "-I/d"]);
+        assert_eq!(libs, ["-la", "-lc", "-ld", "-lb"]);
+        assert!(seen.values().all(|v| *v));
+        drop(std::fs::remove_dir_all(&dir));
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(compare_versions("1.2.11", "1.2"), Ordering::Greater);
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.2.0", "1.2.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0a", "1.0.1"), Ordering::Less);
+        assert_eq!(semver_lossy("1.2.11.1"), semver::Version::new(1, 2, 11));
+        assert_eq!(semver_lossy("3.0"), semver::Version::new(3, 0, 0));
+    }
+}
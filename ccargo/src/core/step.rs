@@ -1,10 +1,12 @@
-use crate::core::{TargetName, PackageId, Layout, Context, fingerprint::DepInfo};
+use crate::cc::host_triple;
+use crate::core::{TargetName, PackageId, Layout, Context, Unit, external, fingerprint::DepInfo};
 use crate::utils::{IResult, InternedString, MsgWriter, paths, BinarySerialize};
 use std::io::{BufRead, BufReader, Write};
 use std::process::{Command, ExitStatus, Stdio};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 
 #[derive(Debug, Hash)]
@@ -12,6 +14,8 @@ pub enum Program {
     Binary(PathBuf),
     Target(TargetName),
     Script{tool: PathBuf, script: PathBuf},
+    // built-in substitution of the values in a template, like CMake's `configure_file`
+    Configure{template: PathBuf, values: BTreeMap<String, String>},
 }
 
 impl From<&str> for Program {
@@ -42,7 +46,12 @@ pub struct StepInner {
      pub outputs: Vec<PathBuf>,
      pub depends: Vec<TargetName>,
      pub program: Program,
+     // arguments of the program, `${VAR}` variables are expanded when the step is run
      pub args: Vec<String>,
+     // environment variables of the program, expanded like `args`
+     pub env: BTreeMap<String, String>,
+     // working directory of the program (default: package root)
+     pub cwd: Option<PathBuf>,
 }
 
 
@@ -55,8 +64,14 @@ impl Step {
         TargetName::new(self.package.name(), self.name)
     }
 
-    pub fn stable_hash<'a>(&self, ws: &'a Path) -> StepStableHash<'a> {
-        StepStableHash(self.clone(), ws)
+    pub fn stable_hash<'a>(&self, cx: &Context, ws: &'a Path) -> IResult<StepStableHash<'a>> {
+        // machine-specific directories in the expanded arguments do not change the hash
+        let target_root = cx.layout.target().display().to_string();
+        let root = ws.display().to_string();
+        let relative = |v: String| v.replace(&target_root, "${TARGET_DIR}").replace(&root, "${PKG_ROOT}");
+        let args = self.expanded_args(cx)?.into_iter().map(relative).collect();
+        let env = self.expanded_env(cx)?.into_iter().map(|(k, v)| (k, relative(v))).collect();
+        Ok(StepStableHash(self.clone(), ws, args, env))
     }
 
     pub fn target(&self) -> Option<&TargetName> {
@@ -79,6 +94,143 @@ impl Step {
         path
     }
 
+    /// Directives recorded in the output file when the step last ran
+    pub fn directives(&self, layout: &Layout) -> IResult<StepDirectives> {
+        let mut directives = StepDirectives::default();
+        let Ok(contents) = std::fs::read(self.output_path(layout)) else {
+            return Ok(directives);
+        };
+        for line in contents.split(|c| *c == b'\n') {
+            match Message::parse(line) {
+                Ok(Message::Define(define)) => {
+                    let (key, value) = match define.split_once('=') {
+                        Some((k, v)) => (k, Some(v.to_string())),
+                        None => (define, None),
+                    };
+                    directives.defines.push((key.to_string(), value));
+                }
+                Ok(Message::Include(path)) => directives.includes.push(path.to_path_buf()),
+                Ok(Message::LinkLib(name)) => directives.links.push(name.to_string()),
+                Ok(Message::LinkSearch(path)) => directives.search_paths.push(path.to_path_buf()),
+                _ => {}
+            }
+        }
+        Ok(directives)
+    }
+
+    /// Print the output recorded in the output file when the step last ran, used when it is fresh
+    pub fn replay<O: Write, E: Write>(
+        &self,
+        layout: &Layout,
+        mut stdout: MsgWriter<O>,
+        mut stderr: MsgWriter<E>,
+    ) -> IResult<()> {
+        let Ok(contents) = std::fs::read(self.output_path(layout)) else {
+            return Ok(());
+        };
+        for line in contents.split_inclusive(|c| *c == b'\n') {
+            match Message::parse(line.strip_suffix(b"\n").unwrap_or(line)) {
+                Ok(Message::Raw(line)) => writeln!(stdout, "{line}")?,
+                Ok(Message::Warning(msg)) => writeln!(stderr, "ccargo:warning={msg}")?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// File written by a `configure` step, its directory is added as an include to dependent targets.
+    /// Every step has its own directory, so templates with the same file name do not overwrite each other
+    pub fn configure_output(&self, layout: &Layout) -> Option<PathBuf> {
+        let Program::Configure { template, .. } = &self.program else {
+            return None;
+        };
+        let name = template.file_name().unwrap().to_string_lossy();
+        let name = name.strip_suffix(".in").unwrap_or(&name);
+        Some(layout.output_dir(&self.package).join(format!("{}.configure", self.name)).join(name))
+    }
+
+    /// Contents of the file written by a `configure` step, values are looked up in the `values`
+    /// of the step, the package metadata, the profile, the target triple and the environment
+    pub fn configure_contents(&self, cx: &Context) -> IResult<Option<String>> {
+        let Program::Configure { template, values } = &self.program else {
+            return Ok(None);
+        };
+        let template = paths::read_string(template)?;
+        let version = self.package.version();
+        let builtin = |name: &str| Some(match name {
+            "PACKAGE_NAME" => self.package.name().to_string(),
+            "PACKAGE_VERSION" => version.to_string(),
+            "PACKAGE_VERSION_MAJOR" => version.major.to_string(),
+            "PACKAGE_VERSION_MINOR" => version.minor.to_string(),
+            "PACKAGE_VERSION_PATCH" => version.patch.to_string(),
+            "PROFILE" => cx.profile.dir_name.to_string(),
+            "DEBUG" => (cx.profile.debug as u8).to_string(),
+            "TARGET" => cx.toolchain.target().to_string(),
+            _ => return None,
+        });
+        Ok(Some(configure(&template, |name| {
+            values.get(name).cloned()
+                .or_else(|| builtin(name))
+                .or_else(|| std::env::var(name).ok())
+        })))
+    }
+
+    /// Arguments with `${OUT_DIR}`, `${PKG_ROOT}`, `${TARGET}`, `${PROFILE}`, `${target:<name>}`
+    /// (output path of another unit) and environment variables expanded
+    pub fn expanded_args(&self, cx: &Context) -> IResult<Vec<String>> {
+        self.args.iter().map(|arg| expand(arg, |name| self.lookup(cx, name))).collect()
+    }
+
+    /// `env` of the step with variables expanded like the arguments
+    pub fn expanded_env(&self, cx: &Context) -> IResult<Vec<(String, String)>> {
+        self.env.iter()
+            .map(|(k, v)| Ok((k.clone(), expand(v, |name| self.lookup(cx, name))?)))
+            .collect()
+    }
+
+    // Path of the program or script with variables expanded like the arguments
+    fn expanded_path(&self, cx: &Context, path: &Path) -> IResult<PathBuf> {
+        Ok(PathBuf::from(expand(&path.to_string_lossy(), |name| self.lookup(cx, name))?))
+    }
+
+    // Value of a `${VAR}` in the program, arguments or `env` of the step
+    fn lookup(&self, cx: &Context, name: &str) -> IResult<String> {
+        if let Some(unit) = name.strip_prefix("target:") {
+            let path = match cx.units.get(&unit_name(unit, self.package), &self.package) {
+                Unit::Target(target) => target.output_path(cx.layout, cx.toolchain.target()),
+                Unit::Step(step) => step.configure_output(cx.layout)
+                    .or_else(|| step.outputs.first().cloned())
+                    .unwrap_or_else(|| step.output_path(cx.layout)),
+            };
+            return Ok(path.display().to_string());
+        }
+        Ok(match name {
+            "OUT_DIR" => cx.layout.output_dir(&self.package).display().to_string(),
+            "PKG_ROOT" => self.package.root().display().to_string(),
+            "TARGET" => cx.toolchain.target().to_string(),
+            "PROFILE" => cx.profile.dir_name.to_string(),
+            _ => match std::env::var(name) {
+                Ok(v) => v,
+                Err(_) => anyhow::bail!("undefined variable `${{{name}}}` in step `{}`", self.full_name()),
+            },
+        })
+    }
+
+    // Like a cargo build script, the program is told where to write and which toolchain is used,
+    // the `env` of the step is applied last so it can override these
+    fn apply_env(&self, cx: &Context, cmd: &mut Command) -> IResult<()> {
+        cmd.env("OUT_DIR", cx.layout.output_dir(&self.package))
+            .env("CCARGO_PKG_NAME", self.package.name().as_str())
+            .env("CCARGO_PKG_VERSION", self.package.version().to_string())
+            .env("TARGET", cx.toolchain.target())
+            .env("HOST", host_triple())
+            .env("PROFILE", cx.profile.dir_name.as_str())
+            .env("NUM_JOBS", rayon::current_num_threads().to_string());
+        external::Env::new(cx)?.apply(cmd);
+        cmd.envs(self.expanded_env(cx)?);
+        Ok(())
+    }
+
     pub fn dep_info_path(&self, layout: &Layout) -> PathBuf {
         let mut path = layout.fingerprint();
         path.push(&self.package.unique_name());
@@ -99,6 +251,22 @@ impl Step {
         mut stdout: MsgWriter<O>,
         mut stderr: MsgWriter<E>,
     ) -> IResult<ExitStatus> {        
+        // templates are configured without running a process
+        if let Program::Configure { template, .. } = &self.program {
+            let path = self.configure_output(cx.layout).unwrap();
+            paths::create_dir_all(path.parent().unwrap())?;
+            paths::write_if_changed(&path, self.configure_contents(cx)?.unwrap())?;
+            paths::write_create_all(self.output_path(cx.layout), b"")?;
+
+            // the template is tracked like a `rerun-if-changed` path
+            let mut dep_info = DepInfo::default();
+            if let Ok(rel) = template.strip_prefix(self.package.root()) {
+                dep_info.add_pkg_relative(rel.to_path_buf());
+            }
+            paths::write_create_all(self.dep_info_path(cx.layout), dep_info.to_bytes())?;
+            return Ok(ExitStatus::default());
+        }
+
         let program = match &self.program {
             Program::Target(name) => {
                 let target = cx.units.get(name, &self.package).as_target().unwrap();
@@ -106,48 +274,62 @@ impl Step {
             }
             Program::Binary(path) => {
                 // TODO: try find path in tools dir
-                path.clone()
+                // programs in the package are found from the package root, even with a `cwd`
+                let path = self.expanded_path(cx, path)?;
+                if path.components().count() > 1 {
+                    paths::abs(path, self.package.root())
+                } else {
+                    path
+                }
             }
             Program::Script { tool, .. } => {
                 // TODO: try to find script in tools dir
                 tool.clone()
             }
+            Program::Configure { .. } => unreachable!(),
         };
         
         let mut cmd = Command::new(program);
+        let root = self.package.root();
+        let cwd = self.cwd.as_deref().unwrap_or(root);
         
         // If executing a script then the first argument is the script path
         if let Program::Script { script, .. } = &self.program {
-            cmd.arg(script);
+            cmd.arg(paths::abs(self.expanded_path(cx, script)?, root));
         }
         
-        let root = self.package.root();
+        self.apply_env(cx, &mut cmd)?;
         let mut child = cmd
-            .args(&self.args)
-            .current_dir(root)
+            .args(self.expanded_args(cx)?)
+            .current_dir(cwd)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()?;
 
-        // TODO: Cache output for step and replay when fresh?
+        // output and directives applied to dependents (with paths made absolute) are recorded
+        // in the order they are read from stdout and stderr
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+
+        // stderr is read on its own thread so neither pipe fills up
         let buf_stderr = BufReader::new(child.stderr.take().unwrap());
-        rayon::spawn(move || {
-            for mut line in buf_stderr.split(b'\n').filter_map(|l| l.ok()) {
-                if *line.last().unwrap() == b'\r' { 
-                    line.pop(); 
+        let mut warnings = stderr.clone();
+        let stderr_thread = {
+            let recorded = recorded.clone();
+            std::thread::spawn(move || {
+                for line in read_lines(buf_stderr) {
+                    drop(write!(stderr, "ccargo:warning="));
+                    drop(stderr.write_all(&line));
+                    drop(writeln!(stderr, ""));
+                    recorded.lock().unwrap().push(format!("ccargo:warning={}", String::from_utf8_lossy(&line)));
                 }
-                drop(write!(stderr, "ccargo:warning="));
-                drop(stderr.write_all(&line));
-                drop(writeln!(stderr, ""));
-            }
-        });
+            })
+        };
 
         let mut dep_info = DepInfo::default();
+        let mut errors = Vec::new();
         let buf_stdout = BufReader::new(child.stdout.take().unwrap());
-        for mut line in buf_stdout.split(b'\n').filter_map(|l| l.ok()) {
-            if *line.last().unwrap() == b'\r' { 
-                line.pop();
-            }
+        for line in read_lines(buf_stdout) {
+            let mut recorded = recorded.lock().unwrap();
             // TODO: Better error message for parsing of step output
             match Message::parse(&line) {
                 Err(e) => {
@@ -155,21 +337,48 @@ impl Step {
                 }
                 Ok(Message::Raw(line)) => { 
                     drop(writeln!(stdout, "{}", line));
+                    recorded.push(line.to_string());
                 }
                 Ok(Message::RerunIfChanged(path)) => {
-                    if let Ok(rel) = paths::abs(path, root).strip_prefix(root) {
+                    if let Ok(rel) = paths::abs(path, cwd).strip_prefix(root) {
                         dep_info.add_pkg_relative(rel.to_path_buf());
                     } else {
                         drop(writeln!(stdout, "Path `{:?}` was ignored as it is outside of the package root: `{:?}`", path, root));
                     }
                 }
+                Ok(Message::RerunIfEnvChanged(name)) => {
+                    dep_info.add_env(name.to_string(), std::env::var(name).ok());
+                }
+                Ok(Message::Warning(msg)) => {
+                    drop(writeln!(warnings, "ccargo:warning={msg}"));
+                    recorded.push(format!("ccargo:warning={msg}"));
+                }
+                Ok(Message::Error(msg)) => {
+                    errors.push(msg.to_string());
+                }
+                Ok(Message::Define(define)) => recorded.push(format!("ccargo:define={define}")),
+                Ok(Message::Include(path)) => {
+                    recorded.push(format!("ccargo:include={}", paths::abs(path, cwd).display()));
+                }
+                Ok(Message::LinkLib(name)) => recorded.push(format!("ccargo:link-lib={name}")),
+                Ok(Message::LinkSearch(path)) => {
+                    recorded.push(format!("ccargo:link-search={}", paths::abs(path, cwd).display()));
+                }
             }
         }
 
         let status = child.wait()?;
+        stderr_thread.join()
+            .map_err(|_| anyhow::anyhow!("failed to read the stderr of step `{}`", self.full_name()))?;
+        let recorded = std::mem::take(&mut *recorded.lock().unwrap());
+        if !errors.is_empty() {
+            anyhow::bail!("Step `{}` failed:\n{}", self.full_name(), errors.join("\n"))
+        }
 
+        // the output file is replayed and its directives keep applying to dependents when the step is fresh
         let output = self.output_path(cx.layout);
-        paths::write_create_all(&output, b"")?;
+        let contents = recorded.iter().map(|v| format!("{v}\n")).collect::<String>();
+        paths::write_create_all(&output, contents)?;
 
         if !dep_info.is_empty() {
             paths::write_create_all(self.dep_info_path(cx.layout), dep_info.to_bytes())?;
@@ -207,14 +416,20 @@ impl std::fmt::Debug for Step {
 }
 
 
-pub struct StepStableHash<'a>(Step, &'a Path);
+// expanded arguments and env of the step
+pub struct StepStableHash<'a>(Step, &'a Path, Vec<String>, Vec<(String, String)>);
 
 impl<'a> std::hash::Hash for StepStableHash<'a> {
     fn hash<S: std::hash::Hasher>(&self, state: &mut S) {
         self.0.name.hash(state);
         self.0.depends.hash(state);
         self.0.package.stable_hash(self.1).hash(state);
-        self.0.args.hash(state);
+        self.2.hash(state);
+        self.3.hash(state);
+        self.0.cwd
+            .as_ref()
+            .map(|v| v.strip_prefix(self.1).unwrap_or(v))
+            .hash(state);
         for v in self.0.inputs.iter() {
             v.strip_prefix(self.1).unwrap().hash(state);
         }
@@ -232,29 +447,381 @@ impl<'a> std::hash::Hash for StepStableHash<'a> {
                 tool.strip_prefix(self.1).unwrap_or(&tool).hash(state);
                 script.strip_prefix(self.1).unwrap_or(&script).hash(state);
             }
+            Program::Configure { template, values } => {
+                template.strip_prefix(self.1).unwrap_or(template).hash(state);
+                values.hash(state);
+            }
         }
     }
 }
 
 
+/// Defines, includes and link settings that a step applies to the targets that depend on it
+#[derive(Debug, Default, Hash)]
+pub struct StepDirectives {
+    pub defines: Vec<(String, Option<String>)>,
+    pub includes: Vec<PathBuf>,
+    pub links: Vec<String>,
+    pub search_paths: Vec<PathBuf>,
+}
+
+impl StepDirectives {
+    pub fn extend(&mut self, other: StepDirectives) {
+        self.defines.extend(other.defines);
+        self.includes.extend(other.includes);
+        self.links.extend(other.links);
+        self.search_paths.extend(other.search_paths);
+    }
+}
+
 #[derive(Debug)]
 enum Message<'a> {
     Raw(&'a str),
-    RerunIfChanged(&'a Path),    
+    RerunIfChanged(&'a Path),
+    RerunIfEnvChanged(&'a str),
+    Warning(&'a str),
+    Error(&'a str),
+    Define(&'a str),
+    Include(&'a Path),
+    LinkLib(&'a str),
+    LinkSearch(&'a Path),
 }
 
 impl<'a> Message<'a> {
+    // Directives look like `ccargo:<key>=<value>`, `ccargo:<key>:<value>` is also accepted
     fn parse(line: &'a [u8]) -> IResult<Self> {
         let line = std::str::from_utf8(line)?;
 
-        if let Some(rest) = line.strip_prefix("ccargo:") {
-            if let Some(path) = rest.strip_prefix("rerun-if-changed:") {
-                Ok(Self::RerunIfChanged(Path::new(path)))
+        let Some(rest) = line.strip_prefix("ccargo:") else {
+            return Ok(Self::Raw(line));
+        };
+        let Some((key, value)) = rest.find(['=', ':']).map(|i| (&rest[..i], &rest[i + 1..])) else {
+            anyhow::bail!("Invalid step directive `{}`", line)
+        };
+        Ok(match key {
+            "rerun-if-changed" => Self::RerunIfChanged(Path::new(value)),
+            "rerun-if-env-changed" => Self::RerunIfEnvChanged(value),
+            "warning" => Self::Warning(value),
+            "error" => Self::Error(value),
+            "define" => Self::Define(value),
+            "include" => Self::Include(Path::new(value)),
+            "link-lib" => Self::LinkLib(value),
+            "link-search" => Self::LinkSearch(Path::new(value)),
+            _ => anyhow::bail!("Invalid step directive `{}`", line),
+        })
+    }
+}
+
+
+/// Units referenced by `${target:<name>}` in the arguments of a step, the step depends on them
+pub fn arg_units(args: &[String], package: PackageId) -> Vec<TargetName> {
+    let mut units = Vec::new();
+    for arg in args {
+        // variables are found the same way they are expanded, so `$${target:x}` is not a unit
+        drop(expand(arg, |name| {
+            if let Some(unit) = name.strip_prefix("target:") {
+                units.push(unit_name(unit, package));
+            }
+            Ok(String::new())
+        }));
+    }
+    units
+}
+
+// `pkg::name` or the name of a unit in `package`
+fn unit_name(name: &str, package: PackageId) -> TargetName {
+    TargetName::from_str(name).unwrap_or_else(|_| TargetName::new(package.name(), name))
+}
+
+// Replace `${VAR}` with its value, `$$` is a literal `$`
+fn expand(arg: &str, mut lookup: impl FnMut(&str) -> IResult<String>) -> IResult<String> {
+    let mut out = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if let Some(v) = tail.strip_prefix("$$") {
+            out.push('$');
+            rest = v;
+        } else if let Some(v) = tail.strip_prefix("${") {
+            let Some(end) = v.find('}') else {
+                anyhow::bail!("unterminated variable in argument `{arg}`")
+            };
+            out.push_str(&lookup(&v[..end])?);
+            rest = &v[end + 1..];
+        } else {
+            out.push('$');
+            rest = &tail[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Split a command into words like a POSIX shell, without expanding variables
+pub fn split_command(command: &str) -> IResult<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => {
+                // escaped newlines continue the line
+                match chars.next() {
+                    Some('\n') | None => {}
+                    Some(c) => word.get_or_insert_with(String::new).push(c),
+                }
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => anyhow::bail!("unterminated single quote in command `{command}`"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // only these characters can be escaped inside double quotes
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('\\' | '"' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => { word.push('\\'); word.push(c); }
+                            None => anyhow::bail!("unterminated double quote in command `{command}`"),
+                        },
+                        Some(c) => word.push(c),
+                        None => anyhow::bail!("unterminated double quote in command `{command}`"),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+
+// Lines of the output of a process without line endings, blank lines are skipped
+fn read_lines<R: BufRead>(reader: R) -> impl Iterator<Item = Vec<u8>> {
+    reader.split(b'\n')
+        .map_while(Result::ok)
+        .map(|mut line| {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            line
+        })
+        .filter(|line| !line.is_empty())
+}
+
+
+// Substitute `@VAR@`/`${VAR}` and `#cmakedefine VAR`/`#cmakedefine01 VAR` lines like CMake's `configure_file`
+fn configure(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    for line in template.split_inclusive('\n') {
+        let (text, eol) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        let directive = text.trim_start().strip_prefix('#').map(str::trim_start);
+        if let Some(rest) = directive.and_then(|v| v.strip_prefix("cmakedefine01")) {
+            let name = rest.split_whitespace().next().unwrap_or_default();
+            out.push_str(&format!("#define {name} {}{eol}", is_true(lookup(name)) as u8));
+        } else if let Some(rest) = directive.and_then(|v| v.strip_prefix("cmakedefine")) {
+            let name = rest.split_whitespace().next().unwrap_or_default();
+            if is_true(lookup(name)) {
+                out.push_str(&format!("#define {}{eol}", substitute(rest.trim_start(), &lookup)));
             } else {
-                anyhow::bail!("Invalid step directive `{}`", line)
+                out.push_str(&format!("/* #undef {name} */{eol}"));
             }
         } else {
-            Ok(Self::Raw(line))
+            out.push_str(&substitute(text, &lookup));
+            out.push_str(eol);
         }
     }
+    out
+}
+
+// Replace `@VAR@` and `${VAR}` with their values, undefined variables are replaced with nothing
+fn substitute(line: &str, lookup: &impl Fn(&str) -> Option<String>) -> String {
+    let is_name = |v: &str| !v.is_empty() && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(i) = rest.find(['@', '$']) {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        let var = if let Some(v) = tail.strip_prefix("${") {
+            v.find('}').map(|end| (&v[..end], end + 3))
+        } else if let Some(v) = tail.strip_prefix('@') {
+            v.find('@').map(|end| (&v[..end], end + 2))
+        } else {
+            None
+        };
+        match var {
+            Some((name, len)) if is_name(name) => {
+                out.push_str(&lookup(name).unwrap_or_default());
+                rest = &tail[len..];
+            }
+            _ => {
+                out.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Whether a value is true for `#cmakedefine` (defined and not one of CMake's false constants)
+fn is_true(value: Option<String>) -> bool {
+    value.is_some_and(|v| {
+        let v = v.to_uppercase();
+        !(v.is_empty() || v.ends_with("NOTFOUND") || ["0", "OFF", "NO", "FALSE", "N", "IGNORE"].contains(&v.as_str()))
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cc::{Language, Profile, Toolchain};
+    use crate::core::{Config, SourceId};
+    use crate::toml::load_packages;
+
+    #[test]
+    fn configure_template() {
+        let values = BTreeMap::from([
+            ("NAME", "demo"), ("VERSION", "1.2"), ("HAVE_A", "ON"), ("HAVE_B", "0"),
+        ]);
+        let lookup = |name: &str| values.get(name).map(|v| v.to_string());
+        let template = "\
+            #define NAME \"@NAME@\"\n\
+            #define VERSION \"${VERSION}\" // user@example.com $5 @MISSING@\n\
+            #cmakedefine HAVE_A @VERSION@\n\
+            #  cmakedefine HAVE_B\n\
+            #cmakedefine01 HAVE_A\n\
+            #cmakedefine01 HAVE_C";
+        assert_eq!(configure(template, lookup), "\
+            #define NAME \"demo\"\n\
+            #define VERSION \"1.2\" // user@example.com $5 \n\
+            #define HAVE_A 1.2\n\
+            /* #undef HAVE_B */\n\
+            #define HAVE_A 1\n\
+            #define HAVE_C 0");
+    }
+
+    #[test]
+    fn configure_outputs_per_step() {
+        let package = PackageId::new("demo", "0.1.0", SourceId::new(PathBuf::from("/demo"))).unwrap();
+        let step = |name: &str, template: &str| Step::new(StepInner{
+            name: name.into(),
+            package,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            depends: Vec::new(),
+            program: Program::Configure{template: template.into(), values: BTreeMap::new()},
+            args: Vec::new(),
+            env: BTreeMap::new(),
+            cwd: None,
+        });
+        let layout = Layout::new("/demo", &Profile::dev(), None);
+        let out = layout.output_dir(&package);
+        let (a, b) = (step("a", "/demo/a/config.h.in"), step("b", "/demo/b/config.h.in"));
+        assert_eq!(a.configure_output(&layout).unwrap(), out.join("a.configure").join("config.h"));
+        assert_eq!(b.configure_output(&layout).unwrap(), out.join("b.configure").join("config.h"));
+    }
+
+    #[test]
+    fn split_and_expand_command() {
+        assert_eq!(
+            split_command(r#"gen.py  "a b" 'c "d"' e\ f "x\"y\z" ${OUT_DIR}/out.c ''"#).unwrap(),
+            ["gen.py", "a b", "c \"d\"", "e f", "x\"y\\z", "${OUT_DIR}/out.c", ""],
+        );
+        assert!(split_command("gen.py 'a").is_err());
+
+        let lookup = |name: &str| match name {
+            "OUT_DIR" => Ok("/out".to_string()),
+            _ => anyhow::bail!("undefined `{name}`"),
+        };
+        assert_eq!(expand("-o${OUT_DIR}/a.c $$5 $x", &lookup).unwrap(), "-o/out/a.c $5 $x");
+        assert!(expand("${MISSING}", &lookup).is_err());
+        assert!(expand("${OUT_DIR", &lookup).is_err());
+
+        let package = PackageId::new("demo", "0.1.0", SourceId::new(PathBuf::from("/demo"))).unwrap();
+        let args = ["${PKG_ROOT}/gen.sh", "${target:gen}/a.c", "$${target:escaped}", "-I${target:dep::lib}"].map(String::from);
+        assert_eq!(arg_units(&args, package), [TargetName::new("demo", "gen"), TargetName::new("dep", "lib")]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_with_env_and_cwd() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join("ccargo-test-step-env");
+        drop(std::fs::remove_dir_all(&root));
+        paths::write_create_all(root.join("src").join("a.c"), "int a;\n").unwrap();
+        paths::create_dir_all(root.join("work")).unwrap();
+        // programs with a directory are found from the package root, even with a `cwd`
+        let print = root.join("tools").join("print");
+        paths::write_create_all(&print, "#!/bin/sh\necho \"$1|$CCARGO_PKG_NAME|$TARGET|$PROFILE|$GEN|$OUT_DIR|$CC|$(pwd)\"\n").unwrap();
+        std::fs::set_permissions(&print, std::fs::Permissions::from_mode(0o755)).unwrap();
+        paths::write(root.join("CCargo.toml"), r#"
+[package]
+name = "steps"
+version = "0.1.0"
+
+[[lib]]
+name = "a"
+sources = ["src/a.c"]
+depends-private = ["env"]
+
+[[step]]
+name = "env"
+command = "tools/print ${TARGET}"
+cwd = "work"
+env = { GEN = "${PKG_ROOT}/gen", PROFILE = "custom" }
+"#).unwrap();
+
+        let config = Config::default().unwrap();
+        let toolchain = Toolchain::default().unwrap();
+        let target = toolchain.target();
+        let (package, packages) = load_packages(&root.join("CCargo.toml"), &config, target).unwrap();
+        let profile = Profile::dev();
+        let layout = Layout::new(&root, &profile, None);
+        let cx = Context::new(&config, &layout, &toolchain, &profile, &packages, &package, &["a".to_string()]).unwrap();
+        cx.compile().unwrap();
+
+        let step = &package.steps[0];
+        let cc = toolchain.tools_for(Language::C).unwrap().cc.path().display().to_string();
+        let out_dir = layout.output_dir(&step.package).display().to_string();
+        let expected = format!(
+            "{target}|steps|{target}|custom|{root}/gen|{out_dir}|{cc}|{root}/work\n",
+            root = root.display(),
+        );
+        assert_eq!(paths::read_string(step.output_path(&layout)).unwrap(), expected);
+        drop(std::fs::remove_dir_all(&root));
+    }
+
+    #[test]
+    fn read_output_lines() {
+        let lines = read_lines(&b"a\r\n\n\r\nb\nc"[..]).collect::<Vec<_>>();
+        assert_eq!(lines, [&b"a"[..], b"b", b"c"]);
+    }
+
+    #[test]
+    fn parse_directives() {
+        assert!(matches!(Message::parse(b"ccargo:define=A=1").unwrap(), Message::Define("A=1")));
+        assert!(matches!(Message::parse(b"ccargo:rerun-if-changed:a.txt").unwrap(), Message::RerunIfChanged(p) if p == Path::new("a.txt")));
+        assert!(matches!(Message::parse(b"ccargo:link-lib=m").unwrap(), Message::LinkLib("m")));
+        assert!(matches!(Message::parse(b"hello: ccargo").unwrap(), Message::Raw("hello: ccargo")));
+        assert!(Message::parse(b"ccargo:unknown=1").is_err());
+        assert!(Message::parse(b"ccargo:define").is_err());
+    }
 }
@@ -131,8 +131,10 @@ impl UnitMap {
 
                 map.units.insert(unit.clone());
 
-                map.unit_outputs
-                    .insert(target.output_path(layout, target_triple), unit.clone());
+                if target.kind.has_output() {
+                    map.unit_outputs
+                        .insert(target.output_path(layout, target_triple), unit.clone());
+                }
 
                 map.unit_map
                     .entry(target.full_name())
@@ -178,7 +180,7 @@ impl UnitMap {
         for unit in self.units.iter() {
             match unit {
                 Unit::Target(target) => {
-                    for source in target.sources.iter() {
+                    for source in target.sources.iter().chain(target.git_describe.iter()) {
                         if let Some(dep) = self.step_outputs.get(source) {
                             g.link(unit.clone(), Unit::Step(dep.clone()));
                         }
@@ -210,8 +212,12 @@ impl UnitMap {
         g.add(unit.clone());
         unit.for_each_dep(|dep| {
             let dep_unit = self.get(dep, &unit.package());
+            // dependencies may form a cycle, so each unit is only visited once
+            let visited = g.contains(dep_unit);
             g.link(unit.clone(), dep_unit.clone());
-            self.build_graph_recursive(dep_unit, g);
+            if !visited {
+                self.build_graph_recursive(dep_unit, g);
+            }
         });
     }
     
@@ -1,4 +1,6 @@
+pub mod build_script;
 pub mod cc;
 pub mod core;
+pub mod reproducible;
 pub mod toml;
 pub mod utils;
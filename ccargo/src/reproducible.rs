@@ -0,0 +1,109 @@
+//! Verification that the outputs of a build do not depend on the directory of the sources
+
+use crate::cc::{Profile, Toolchain};
+use crate::core::{Config, Context, Layout, TargetName};
+use crate::toml::{load_packages, CCARGO_TOML};
+use crate::utils::{IResult, paths};
+use std::path::{Path, PathBuf};
+use anyhow::Context as _;
+
+
+/// Build the `targets` of the package at `root` with the reproducible version of `profile`
+/// from two copies of its sources in different directories. Returns the outputs and objects
+/// that differ between the builds (relative to the target directory) for each target
+pub fn verify(
+    config: &Config,
+    toolchain: &Toolchain,
+    root: &Path,
+    profile: &str,
+    targets: &[String],
+) -> IResult<Vec<(TargetName, Vec<PathBuf>)>> {
+    let dir = root.join("target").join("reproducible");
+    let mut builds = Vec::new();
+    // The second copy is nested deeper, so that paths of different lengths are compared
+    for copy in [dir.join("a"), dir.join("b").join("src")] {
+        // Always build from scratch
+        if copy.exists() {
+            std::fs::remove_dir_all(&copy)
+                .with_context(|| format!("failed to remove `{}`", copy.display()))?;
+        }
+        copy_sources(root, &copy)?;
+        let (package, packages) = load_packages(&copy.join(CCARGO_TOML), config, toolchain.target())?;
+        let profile = match package.profile(profile) {
+            Some(v) => Profile{reproducible: true, ..v},
+            None => anyhow::bail!("profile `{profile}` is not defined"),
+        };
+        let layout = Layout::new(&copy, &profile, None);
+        builds.push((package, packages, profile, layout));
+    }
+
+    let contexts = builds.iter()
+        .map(|(package, packages, profile, layout)| {
+            let cx = Context::new(config, layout, toolchain, profile, packages, package, targets)?;
+            cx.compile()?;
+            Ok(cx)
+        })
+        .collect::<IResult<Vec<_>>>()?;
+    contexts[0].compare_outputs(&contexts[1])
+}
+
+
+// Copy the files of the package at `root` into `dst`, except for its build outputs
+fn copy_sources(root: &Path, dst: &Path) -> IResult<()> {
+    let target = root.join("target");
+    for file in paths::walk_files(root)? {
+        if file.starts_with(&target) {
+            continue;
+        }
+        let to = dst.join(file.strip_prefix(root)?);
+        paths::create_dir_all(to.parent().unwrap())?;
+        std::fs::copy(&file, &to)
+            .with_context(|| format!("failed to copy `{}`", file.display()))?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn verify_builds() {
+        let root = std::env::temp_dir().join("ccargo-test-reproducible");
+        drop(std::fs::remove_dir_all(&root));
+        paths::write_create_all(root.join("src").join("nested").join("a.c"), "int a(void) { return 1; }\n").unwrap();
+        let manifest = r#"
+[package]
+name = "repro"
+version = "0.1.0"
+
+[[lib]]
+name = "a"
+sources = ["src/nested/a.c"]
+"#;
+        paths::write(root.join(CCARGO_TOML), manifest).unwrap();
+        let config = Config::default().unwrap();
+        let toolchain = Toolchain::default().unwrap();
+        let targets = ["a".to_string()];
+        assert_eq!(verify(&config, &toolchain, &root, "dev", &targets).unwrap(), []);
+
+        // The directory of the sources ends up in the object and the library
+        let step = r#"
+[[step]]
+name = "root"
+command = ["sh", "-c", "mkdir -p \"$1\" && printf '#define ROOT \"%s\"\n' \"$0\" > \"$1/root.h\" && echo ccargo:include=$1", "${PKG_ROOT}", "${OUT_DIR}"]
+"#;
+        paths::write(root.join(CCARGO_TOML), format!("{manifest}depends-private = [\"root\"]\n{step}")).unwrap();
+        paths::write(root.join("src").join("nested").join("a.c"), "#include \"root.h\"\nconst char *a = ROOT;\n").unwrap();
+        let differ = verify(&config, &toolchain, &root, "dev", &targets).unwrap();
+        assert_eq!(differ.len(), 1);
+        assert_eq!(differ[0].0.to_string(), "repro::a");
+        let files = differ[0].1.iter().map(|v| v.file_name().unwrap().to_string_lossy().into_owned()).collect::<Vec<_>>();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0], "liba.a");
+        assert!(files[1].ends_with("_a.o"));
+        drop(std::fs::remove_dir_all(&root));
+    }
+}
@@ -1,10 +1,11 @@
 use crate::cc::*;
 use crate::core::*;
 use crate::utils::{paths, IResult, InternedString};
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::path::{Path, PathBuf};
 use serde::{Serialize, Deserialize};
 use semver::Version;
+use ccargo_platform::Platform;
 use anyhow::bail;
 
 // TODO: Validate field names are kebab case
@@ -18,6 +19,7 @@ pub const CCARGO_TOML: &str = "CCargo.toml";
 pub fn read_package(
     path: &Path,
     config: &Config,
+    target: &str,
 ) -> IResult<Package> {
     let contents = paths::read_string(path)?;
 
@@ -34,6 +36,7 @@ pub fn read_package(
     let mut package = manifest.to_real(
         path.parent().unwrap(), 
         config,
+        &target_platform(target),
     )?;
     
     for key in unused {
@@ -72,6 +75,52 @@ pub fn read_package(
 }
 
 
+/// Load the package at `path` and all of its dependencies for the `target` triple
+pub fn load_packages(
+    path: &Path,
+    config: &Config,
+    target: &str,
+) -> IResult<(Package, PackageMap)> {
+    // TODO: Verify all shared targets have a unique name
+    // TODO: Verify there are no runtime lib conflicts
+    // TODO: Verify that target dependencies are only libraries
+    // TODO: Verify all files exist or are step outputs
+    // TODO: Verify every bin target that is depended on by other units has no dylib dependencies itself
+    // TODO: Recursively load CCargo.toml files inside package root dir
+    let mut packages = Vec::new();
+    load_packages_recursive(path, config, target, &mut packages, &mut HashSet::new())?;
+    Ok((
+        // first package is the root
+        packages.first().unwrap().clone(), 
+        PackageMap::from_packages(&packages)
+    ))
+}
+
+fn load_packages_recursive(
+    path: &Path,
+    config: &Config,
+    target: &str,
+    packages: &mut Vec<Package>,
+    seen: &mut HashSet<PathBuf>,
+) -> IResult<()> {
+    if !seen.insert(path.to_path_buf()) {
+        return Ok(());
+    }
+    let pkg = read_package(path, config, target)?;
+    packages.push(pkg.clone());
+    for dep in pkg.dependencies.iter() {
+        if dep.pkg_config.is_some() {
+            if seen.insert(dep.source_id.path().to_path_buf()) {
+                packages.push(pkg_config::load_package(dep)?);
+            }
+            continue;
+        }
+        load_packages_recursive(&dep.source_id.manifest_path(), config, target, packages, seen)?;
+    }
+    Ok(())
+}
+
+
 /// This type is used to deserialize `CCargo.toml` files.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -84,10 +133,14 @@ pub struct TomlManifest {
     bin: Option<Vec<TomlTarget>>,
     // custom build steps
     step: Option<Vec<TomlStep>>,
+    // libraries built by external build systems
+    external: Option<Vec<TomlExternal>>,
     // package dependencies
     dependencies: Option<BTreeMap<String, TomlDependency>>,
     // platform-specific options
     platform: Option<BTreeMap<String, TomlPlatform>>,
+    // build profiles (`dev`/`release`)
+    profile: Option<BTreeMap<InternedString, TomlProfile>>,
 }
 
 
@@ -123,6 +176,33 @@ pub struct TomlPackage {
 }
 
 
+/// Represents a `profile` section of a `CCargo.toml`, e.g. `[profile.release]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TomlProfile {
+    // generate debug information
+    debug: Option<bool>,
+    // link target incrementally
+    incremental: Option<bool>,
+    // use exceptions
+    exceptions: Option<bool>,
+    // optimization level
+    opt_level: Option<OptLevel>,
+    // link-time optimization
+    lto: Option<Lto>,
+    // rpath used for dynamic linking on platforms that support it
+    rpath: Option<InternedString>,
+    // produce outputs that do not depend on the build location or time
+    reproducible: Option<bool>,
+    // information removed from linked binaries (`none`/`debuginfo`/`symbols`)
+    strip: Option<Strip>,
+    // how debug information is stored (`off`/`packed`/`unpacked`)
+    split_debuginfo: Option<SplitDebuginfo>,
+    // linker used instead of the compiler's default (`lld`/`mold`/`gold`/path)
+    linker: Option<Linker>,
+}
+
+
 // Represents an entry in the `dependencies` section of a `CCargo.toml`
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -145,6 +225,10 @@ pub struct TomlDetailedDependency {
     // `path` is relative to the file it appears in. If that's a `CCargo.toml`, it'll be relative to
     // that TOML file, and if it's a `.ccargo/config.toml` file, it'll be relative to that file.
     path: Option<String>,
+    // system library found with pkg-config, e.g. `zlib >= 1.2`
+    pkg_config: Option<String>,
+    // link the pkg-config library statically, adding its `Libs.private` like `pkg-config --static`
+    r#static: Option<bool>,
 }
 
 
@@ -161,6 +245,23 @@ pub struct TomlDetailedDependency {
 /// lib  (shared=true)     -> headers, dynamic linking
 /// lib  (runtime=false)   -> headers, dynamic linking
 /// lib  (runtime=true)    -> headers only (manual runtime loading using dlopen,...)
+/// lib  (no sources)     -> headers, defines, flags and links only (interface library)
+/// lib  (path="...")      -> headers, links prebuilt library
+/// lib  (cargo="...")     -> headers, links rust crate built as `staticlib`/`cdylib`
+/// lib  (link-group=true) -> headers, static linking in a group with libraries it has cyclic dependencies on
+/// lib  (whole-archive=true) -> headers, static linking of all objects, even unreferenced ones
+/// lib  (exports="...")   -> headers, dynamic linking, only the listed symbols are exported
+/// lib  (soversion="...") -> headers, dynamic linking, `libfoo.so.<version>` with soname `libfoo.so.<soversion>`
+/// 
+/// Generated headers
+/// export-header="..."    -> `EXPORT_<name>` macro that exports symbols of shared builds of the library
+/// export-header={...}    -> same, with `path`, `base-name`, `prefix`, `suffix` and `extra-macros` options
+/// version-header=true    -> public `<pkg>_version.h` with `<PKG>_VERSION_MAJOR/MINOR/PATCH/STRING`
+/// version-header="..."   -> same, with `<PKG>_VERSION_GIT` from a `git describe` file written by a step
+/// [[lib.config]]         -> public `<target>_config.h` with the results of `has-header`, `has-function`, 
+///                           `sizeof`, `compiles` and `has-flag` checks (e.g. `HAVE_SYS_EPOLL_H`)
+/// embed=[{file="..."}]   -> public `<target>_embed.h` declaring `<symbol>[]` and `<symbol>_len` for files 
+///                           compiled into the target (symbol defaults to the file name, e.g. `logo_png`)
 /// 
 /// `depends_private` applies the above rules to this target only
 /// 
@@ -185,10 +286,15 @@ pub struct TomlTarget {
     // public/private dependencies required to build this target
     depends_public: Option<Vec<TomlTargetDependency>>,
     depends_private: Option<Vec<TomlTargetDependency>>,
+    // public/private system libraries linked by name (i.e. -lm)
+    links_public: Option<Vec<String>>,
+    links_private: Option<Vec<String>>,
+    // directories searched for system libraries (relative to .toml file)
+    search_paths: Option<Vec<PathBuf>>,
     // options that control the compilation
     options: Option<TomlOptions>,
     // path to export header that defines export macros to be used by shared library functions
-    export_header: Option<PathBuf>,
+    export_header: Option<TomlExportHeader>,
     // platform specific options
     platform: Option<BTreeMap<String, TomlTargetPlatform>>,
 
@@ -201,6 +307,24 @@ pub struct TomlTarget {
     //  `None` or `Some(false)`             -> headers + link
     //  `Some(true)` or `Some("path/lib")`  -> headers only
     runtime: Option<StringOrBool>,
+    // prebuilt library that is linked instead of compiling `sources` (relative to .toml file)
+    path: Option<PathBuf>,
+    // rust crate built as a `staticlib`/`cdylib` instead of compiling `sources` (relative to .toml file)
+    cargo: Option<PathBuf>,
+    // static library that is part of a dependency cycle with other `link-group` libraries
+    link_group: Option<bool>,
+    // static library whose objects are always linked, e.g. for self-registering plugins
+    whole_archive: Option<bool>,
+    // version script or symbol list of the symbols exported by a shared library (relative to .toml file)
+    exports: Option<PathBuf>,
+    // version in the soname of a shared library, defaults to the major version of the package
+    soversion: Option<String>,
+    // generate `<pkg>_version.h`, optionally with the contents of a `git describe` file (relative to .toml file)
+    version_header: Option<StringOrBool>,
+    // feature probes whose results are written to a generated `<target>_config.h`
+    config: Option<Vec<TomlConfigCheck>>,
+    // binary files compiled into the target as byte arrays
+    embed: Option<Vec<TomlEmbed>>,
 }
 
 
@@ -221,6 +345,8 @@ pub struct TomlTargetPlatform {
     depends_private: Option<Vec<TomlTargetDependency>>,
     // options that control the compilation
     options: Option<TomlOptions>,
+    // prebuilt library for this platform (relative to .toml file)
+    path: Option<PathBuf>,
 }
 
 
@@ -230,17 +356,177 @@ pub struct TomlTargetPlatform {
 pub struct TomlStep {
     // name of the step (must be unique per package)
     name: InternedString,
-    // command invoked by the step
-    command: String,
+    // command invoked by the step, a string split like a POSIX shell or an array of arguments,
+    // `${OUT_DIR}`/`${PKG_ROOT}`/`${TARGET}`/`${PROFILE}`/`${target:<name>}` are expanded in the program and arguments
+    command: Option<TomlCommand>,
+    // template whose `@VAR@`/`${VAR}`/`#cmakedefine` placeholders are substituted instead of
+    // running a command, the result is included by dependents (relative to .toml file)
+    configure: Option<PathBuf>,
+    // values substituted into the `configure` template
+    values: Option<BTreeMap<String, StringOrBool>>,
     // input files required by this step (relative to .toml file)
     inputs: Option<Vec<PathBuf>>,
     // output files generated by this step (relative to .toml file)
     outputs: Option<Vec<PathBuf>>,
     // dependencies required by this step
     depends: Option<Vec<TomlTargetDependency>>,
+    // environment variables set for the `command`, expanded like its arguments
+    env: Option<BTreeMap<String, String>>,
+    // working directory of the `command` (relative to .toml file, default: package root)
+    cwd: Option<PathBuf>,
+}
+
+/// Represents the `command` of a step in a `CCargo.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TomlCommand {
+    // e.g. `"gen.py --out '${OUT_DIR}/my file.c'"`
+    Line(String),
+    // e.g. `["gen.py", "--out", "${OUT_DIR}/my file.c"]`
+    Args(Vec<String>),
+}
+
+/// Represents an `external` section of a `CCargo.toml`, a library built by another build system
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TomlExternal {
+    // name of the library target (must be unique per package)
+    name: InternedString,
+    // build system of the project (`cmake`/`make`/`autotools`)
+    kind: ExternalKind,
+    // root directory of the project (relative to .toml file)
+    path: PathBuf,
+    // name of the installed library without prefix/extension (default is `name`)
+    lib: Option<String>,
+    // is the installed library a shared library?
+    shared: Option<bool>,
+    // extra arguments passed when configuring the project
+    args: Option<Vec<String>>,
+    // public compiler defines and system libraries required by the library
+    define_public: Option<BTreeSet<String>>,
+    links_public: Option<Vec<String>>,
+}
+
+/// Represents a target's `export_header` in a `CCargo.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TomlExportHeader {
+    // path of the header (relative to .toml file), e.g. `"include/foo_export.h"`
+    Path(PathBuf),
+    // header with options, e.g. `{ path = "include/foo_export.h", prefix = "", suffix = "_API" }`
+    Detailed {
+        path: PathBuf,
+        // name the macros are derived from (default: target name)
+        #[serde(rename = "base-name")]
+        base_name: Option<String>,
+        // prefix/suffix of the export macro (default: `EXPORT_<base-name>`)
+        prefix: Option<String>,
+        suffix: Option<String>,
+        // also define `<base-name>_NO_EXPORT` and `<base-name>_DEPRECATED[_EXPORT|_NO_EXPORT]`
+        #[serde(rename = "extra-macros")]
+        extra_macros: Option<bool>,
+    },
+}
+
+impl TomlExportHeader {
+    fn to_real(&self, root: &Path, name: &str) -> ExportHeader {
+        match self {
+            Self::Path(path) => ExportHeader{
+                path: paths::abs(path, root),
+                base_name: name.to_string(),
+                prefix: "EXPORT_".to_string(),
+                suffix: String::new(),
+                extra_macros: false,
+            },
+            Self::Detailed { path, base_name, prefix, suffix, extra_macros } => ExportHeader{
+                path: paths::abs(path, root),
+                base_name: base_name.clone().unwrap_or_else(|| name.to_string()),
+                prefix: prefix.clone().unwrap_or_else(|| "EXPORT_".to_string()),
+                suffix: suffix.clone().unwrap_or_default(),
+                extra_macros: extra_macros.unwrap_or(false),
+            },
+        }
+    }
+}
+
 
+/// Represents an entry in a target's `config` array in a `CCargo.toml`, exactly one check is set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TomlConfigCheck {
+    // header that can be included, e.g. `"sys/epoll.h"`
+    has_header: Option<String>,
+    // function that can be linked, e.g. `"strlcpy"`
+    has_function: Option<String>,
+    // type whose size is written, e.g. `"long"`
+    sizeof: Option<String>,
+    // source file that compiles (relative to .toml file)
+    compiles: Option<PathBuf>,
+    // compiler flag that is supported, e.g. `"-mavx2"`
+    has_flag: Option<String>,
+    // name of the define (default: autoconf-style name, e.g. `HAVE_SYS_EPOLL_H`/`SIZEOF_LONG`)
+    define: Option<String>,
 }
 
+impl TomlConfigCheck {
+    fn to_real(&self, root: &Path, target: &str) -> IResult<(String, config_checks::Check)> {
+        use config_checks::Check;
+        let mut checks = Vec::new();
+        checks.extend(self.has_header.clone().map(Check::HasHeader));
+        checks.extend(self.has_function.clone().map(Check::HasFunction));
+        checks.extend(self.sizeof.clone().map(Check::Sizeof));
+        checks.extend(self.compiles.as_ref().map(|v| Check::Compiles(paths::abs(v, root))));
+        checks.extend(self.has_flag.clone().map(Check::HasFlag));
+        if checks.len() != 1 {
+            bail!("each `config` check of target `{target}` must have exactly one of \
+                `has-header`, `has-function`, `sizeof`, `compiles` or `has-flag`")
+        }
+        let check = checks.remove(0);
+        if let Check::Compiles(path) = &check {
+            if !path.exists() {
+                bail!("`config` check of target `{target}` does not exist: `{}`", path.display())
+            }
+        }
+        let define = self.define.clone().unwrap_or_else(|| check.default_define());
+        Ok((define, check))
+    }
+}
+
+
+/// Represents an entry in a target's `embed` array in a `CCargo.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TomlEmbed {
+    // file that is embedded (relative to .toml file)
+    file: PathBuf,
+    // name of the array (default: file name with non-alphanumeric characters replaced, e.g. `logo_png`)
+    symbol: Option<String>,
+}
+
+impl TomlEmbed {
+    fn to_real(&self, root: &Path, target: &str) -> IResult<(PathBuf, String)> {
+        let file = paths::abs(&self.file, root);
+        if !file.is_file() {
+            bail!("embedded file of target `{target}` does not exist: `{}`", file.display())
+        }
+        let symbol = match &self.symbol {
+            Some(v) => v.clone(),
+            None => {
+                let name = file.file_name().unwrap().to_string_lossy()
+                    .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+                if name.starts_with(|c: char| c.is_ascii_digit()) { format!("_{name}") } else { name }
+            }
+        };
+        let valid = symbol.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && symbol.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            bail!("embed symbol `{symbol}` of target `{target}` is not a valid C identifier")
+        }
+        Ok((file, symbol))
+    }
+}
+
+
 /// Represents an entry in a target's `depends_public`/`depends_private` array in a `CCargo.toml`
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -249,6 +535,12 @@ pub enum TomlTargetDependency {
     External(TargetName),
     // dependency local to current project, e.g. `my_local_target`
     Local(InternedString),
+    // dependency with options, e.g. `{ name = "my_local_target", whole-archive = true }`
+    Detailed {
+        name: Box<TomlTargetDependency>,
+        #[serde(rename = "whole-archive")]
+        whole_archive: Option<bool>,
+    },
 }
 
 
@@ -277,6 +569,10 @@ pub struct TomlOptions {
     asm_flags: Option<BTreeSet<String>>,
     // flags for unix-like targets
     unix: Option<TomlUnixFlags>,
+    // linker used instead of the profile's linker (`lld`/`mold`/`gold`/path)
+    linker: Option<Linker>,
+    // export all symbols from an executable so plugins can call back into it
+    export_dynamic: Option<bool>,
 }
 
 
@@ -338,6 +634,7 @@ impl TomlManifest {
         &self,
         root: &Path,
         _config: &Config,
+        platform: &RustcTarget,
     ) -> IResult<Package> {
         let warnings = Vec::new();
 
@@ -357,16 +654,21 @@ impl TomlManifest {
 
         let mut targets = Vec::new();
         let mut steps = Vec::new();
+        if let Some(external) = &self.external {
+            for target in external {
+                targets.push(target.to_real(root, id)?);
+            }
+        }
         if let Some(lib) = &self.lib {
             for target in lib {
                 // validate_target(target, "lib", "library");
-                targets.push(target.to_real(root, id, "lib")?);
+                targets.push(target.to_real(root, id, "lib", platform)?);
             }
         }
         if let Some(bin) = &self.bin {
             for target in bin {
                 // validate_target(target, "bin", "binary");
-                targets.push(target.to_real(root, id, "bin")?);
+                targets.push(target.to_real(root, id, "bin", platform)?);
             }
         }
         if let Some(step) = &self.step {
@@ -379,10 +681,15 @@ impl TomlManifest {
         let mut dependencies = Vec::new();
         if let Some(deps) = &self.dependencies {
             for (name, dep) in deps.iter() {
-                dependencies.push(dep.to_real(name, root));
+                dependencies.push(dep.to_real(name, root)?);
             }
         }
 
+        let mut profiles = BTreeMap::new();
+        for (name, profile) in self.profile.iter().flatten() {
+            profiles.insert(*name, profile.to_real(name)?);
+        }
+
         // TODO: Use platform-specific package information
 
         Ok(Package::new(PackageInner{
@@ -390,21 +697,87 @@ impl TomlManifest {
             targets,
             steps,
             dependencies,
+            profiles,
             warnings,
         }))
     }
 }
 
 impl TomlTarget {
-    fn to_real(&self, root: &Path, package: PackageId, target_kind: &str) -> IResult<Target> {
+    fn to_real(&self, root: &Path, package: PackageId, target_kind: &str, platform: &RustcTarget) -> IResult<Target> {
+        let has_sources = self.sources.as_ref().is_some_and(|v| !v.is_empty());
+        let mut prebuilt = self.path.as_ref().map(|v| paths::abs(v, root));
+        for (key, settings) in self.platform.iter().flatten() {
+            if let Some(v) = &settings.path {
+                if platform_matches(key, platform)? {
+                    prebuilt = Some(paths::abs(v, root));
+                }
+            }
+        }
+        if let Some(path) = &prebuilt {
+            if target_kind == "bin" {
+                bail!("binary target `{}` cannot use a prebuilt `path`", self.name)
+            }
+            if has_sources {
+                bail!("prebuilt library `{}` cannot have `sources`", self.name)
+            }
+            if !path.exists() {
+                bail!("prebuilt library `{}` does not exist: `{}`", self.name, path.display())
+            }
+        }
+
+        let cargo = self.cargo.as_ref().map(|v| paths::abs(v, root));
+        if let Some(path) = &cargo {
+            if target_kind == "bin" {
+                bail!("binary target `{}` cannot be built with `cargo`", self.name)
+            }
+            if has_sources || prebuilt.is_some() {
+                bail!("library `{}` built with `cargo` cannot have `sources` or `path`", self.name)
+            }
+            if !path.join("Cargo.toml").exists() {
+                bail!("crate of library `{}` does not exist: `{}`", self.name, path.display())
+            }
+        }
+
+        // prebuilt libraries are shared if their file looks like a dynamic library
+        let shared = self.shared.unwrap_or_else(|| {
+            prebuilt.as_ref().is_some_and(|v| is_dynamic_library(v))
+        });
+
         let kind = if target_kind == "bin" {
             TargetKind::Bin
-        } else if self.runtime.is_some() || self.shared.unwrap_or(false) {
+        } else if !has_sources && prebuilt.is_none() && cargo.is_none() {
+            if self.runtime.is_some() || shared {
+                bail!("library `{}` has no `sources` and cannot be shared", self.name)
+            }
+            TargetKind::Interface
+        } else if self.runtime.is_some() || shared {
             TargetKind::Shared
         } else {
             TargetKind::Static
         };
 
+        let link_group = self.link_group.unwrap_or(false);
+        if link_group && kind != TargetKind::Static {
+            bail!("only static libraries can be in a `link-group`, `{}` is not", self.name)
+        }
+        let whole_archive = self.whole_archive.unwrap_or(false);
+        if whole_archive && kind != TargetKind::Static {
+            bail!("only static libraries can be linked as a `whole-archive`, `{}` is not", self.name)
+        }
+        let exports = self.exports.as_ref().map(|v| paths::abs(v, root));
+        if let Some(path) = &exports {
+            if kind != TargetKind::Shared {
+                bail!("only shared libraries can have `exports`, `{}` is not", self.name)
+            }
+            if !path.exists() {
+                bail!("exports of library `{}` do not exist: `{}`", self.name, path.display())
+            }
+        }
+        if self.soversion.is_some() && kind != TargetKind::Shared {
+            bail!("only shared libraries can have a `soversion`, `{}` is not", self.name)
+        }
+
         let options = if let Some(options) = self.options.as_ref() {
             options.to_real(kind)
         } else {
@@ -420,12 +793,38 @@ impl TomlTarget {
         };
 
         let export_header = self.export_header.as_ref()
-            .map(|v| paths::abs(v, root));
+            .map(|v| v.to_real(root, &self.name));
+
+        let config_checks = self.config.iter()
+            .flatten()
+            .map(|v| v.to_real(root, &self.name))
+            .collect::<IResult<Vec<_>>>()?;
+
+        let embeds = self.embed.iter()
+            .flatten()
+            .map(|v| v.to_real(root, &self.name))
+            .collect::<IResult<Vec<(PathBuf, String)>>>()?;
+        if !embeds.is_empty() && (!kind.has_output() || prebuilt.is_some() || cargo.is_some()) {
+            bail!("only targets compiled from `sources` can `embed` files, `{}` is not", self.name)
+        }
+        for (i, (_, symbol)) in embeds.iter().enumerate() {
+            if embeds[..i].iter().any(|(_, v)| v == symbol) {
+                bail!("embed symbol `{symbol}` is used more than once in target `{}`", self.name)
+            }
+        }
+
+        let (version_header, git_describe) = match &self.version_header {
+            Some(StringOrBool::String(v)) => (true, Some(paths::abs(v, root))),
+            Some(StringOrBool::Bool(v)) => (*v, None),
+            None => (false, None),
+        };
 
         let mut sources = Vec::new();
         let mut includes = Vec::new();
         let mut defines = Vec::new();
         let mut depends = Vec::new();
+        let mut links = Vec::new();
+        let mut search_paths = Vec::new();
         for v in self.sources.as_ref().unwrap_or(&Vec::new()) {
             sources.push(paths::abs(v, root));
         }
@@ -447,11 +846,31 @@ impl TomlTarget {
         for v in self.depends_private.as_ref().unwrap_or(&Vec::new()) {
             depends.push(PublicPrivate::private(v.to_real(package)));
         }
+        let whole_archive_deps = self.depends_public.iter().flatten()
+            .chain(self.depends_private.iter().flatten())
+            .filter(|v| v.whole_archive())
+            .map(|v| v.to_real(package))
+            .collect();
+        for v in self.links_public.as_ref().unwrap_or(&Vec::new()) {
+            links.push(PublicPrivate::public(v.clone()));
+        }
+        for v in self.links_private.as_ref().unwrap_or(&Vec::new()) {
+            links.push(PublicPrivate::private(v.clone()));
+        }
+        for v in self.search_paths.as_ref().unwrap_or(&Vec::new()) {
+            search_paths.push(paths::abs(v, root));
+        }
 
-        if kind == TargetKind::Shared {
-            let name = self.name.as_str();
-            defines.push(PublicPrivate::public((format!("{name}_SHARED"), None)));
-            defines.push(PublicPrivate::private((format!("{name}_EXPORTS"), None)));
+        // Macros checked by the generated export header
+        if let Some(header) = &export_header {
+            let base_name = &header.base_name;
+            if kind == TargetKind::Shared {
+                defines.push(PublicPrivate::public((format!("{base_name}_SHARED"), None)));
+                defines.push(PublicPrivate::private((format!("{base_name}_EXPORTS"), None)));
+            } else if kind == TargetKind::Static {
+                // static builds can share the export header of a shared build of the same library
+                defines.push(PublicPrivate::public((format!("{base_name}_STATIC"), None)));
+            }
         }
 
         // TODO: Use platform-specific target information
@@ -465,17 +884,115 @@ impl TomlTarget {
             defines,
             includes,
             depends,
+            links,
+            search_paths,
             rpath,
             export_header,
+            link_group,
+            whole_archive,
+            whole_archive_deps,
+            exports,
+            soversion: self.soversion.clone(),
+            version_header,
+            git_describe,
+            config_checks,
+            embeds,
+            prebuilt,
+            pkg_config_files: Vec::new(),
+            external: match cargo {
+                Some(path) => Some(External{
+                    kind: ExternalKind::Cargo,
+                    lib: external::cargo_lib_name(&path)?,
+                    path,
+                    args: Vec::new(),
+                }),
+                None => None,
+            },
+        }))
+    }
+}
+
+impl TomlExternal {
+    fn to_real(&self, root: &Path, package: PackageId) -> IResult<Target> {
+        let path = paths::abs(&self.path, root);
+        if !path.is_dir() {
+            bail!("external project `{}` does not exist: `{}`", self.name, path.display())
+        }
+
+        let kind = if self.shared.unwrap_or(false) {
+            TargetKind::Shared
+        } else {
+            TargetKind::Static
+        };
+
+        let mut defines = Vec::new();
+        let mut links = Vec::new();
+        for v in self.define_public.iter().flatten() {
+            defines.push(PublicPrivate::public((v.clone(), None)));
+        }
+        for v in self.links_public.iter().flatten() {
+            links.push(PublicPrivate::public(v.clone()));
+        }
+
+        Ok(Target::new(TargetInner{
+            defines,
+            links,
+            external: Some(External{
+                kind: self.kind,
+                path,
+                lib: self.lib.clone().unwrap_or_else(|| self.name.to_string()),
+                args: self.args.clone().unwrap_or_default(),
+            }),
+            ..TargetInner::new(self.name, kind, package)
         }))
     }
 }
 
 impl TomlStep {
     fn to_real(&self, root: &Path, package: PackageId, targets: &[Target]) -> IResult<Step> {
-        let (program, args) = Self::parse_program(&self.command, targets)?;
-
         let mut inputs = Vec::new();
+        let mut units = Vec::new();
+        let (program, args) = match (&self.command, &self.configure) {
+            (Some(command), None) => {
+                let mut words = match command {
+                    TomlCommand::Line(v) => split_command(v)?,
+                    TomlCommand::Args(v) => v.clone(),
+                };
+                if words.is_empty() {
+                    bail!("`command` of step `{}` is empty", self.name)
+                }
+                // units used by the program or the arguments are built before the step
+                units = arg_units(&words, package);
+                let args = words.split_off(1);
+                (Self::parse_program(&words[0], targets), args)
+            }
+            (None, Some(template)) => {
+                let template = paths::abs(template, root);
+                if !template.exists() {
+                    bail!("template of step `{}` does not exist: `{}`", self.name, template.display())
+                }
+                let values = self.values.iter()
+                    .flatten()
+                    .map(|(k, v)| (k.clone(), match v {
+                        StringOrBool::String(v) => v.clone(),
+                        StringOrBool::Bool(v) => (*v as u8).to_string(),
+                    }))
+                    .collect();
+                inputs.push(template.clone());
+                (Program::Configure{template, values}, Vec::new())
+            }
+            _ => bail!("step `{}` must have exactly one of `command` or `configure`", self.name),
+        };
+        if self.configure.is_some() && (self.env.is_some() || self.cwd.is_some()) {
+            bail!("only steps with a `command` can have `env` or `cwd`, `{}` does not", self.name)
+        }
+        let cwd = self.cwd.as_ref().map(|v| paths::abs(v, root));
+        if let Some(path) = &cwd {
+            if !path.is_dir() {
+                bail!("working directory of step `{}` does not exist: `{}`", self.name, path.display())
+            }
+        }
+
         let mut outputs = Vec::new();
         let mut depends = Vec::new();
         for v in self.inputs.as_ref().unwrap_or(&Vec::new()) {
@@ -487,6 +1004,11 @@ impl TomlStep {
         for v in self.depends.as_ref().unwrap_or(&Vec::new()) {
             depends.push(v.to_real(package));
         }
+        for v in units {
+            if !depends.contains(&v) {
+                depends.push(v);
+            }
+        }
 
         Ok(Step::new(StepInner{
             name: self.name,
@@ -496,19 +1018,12 @@ impl TomlStep {
             depends,
             program,
             args,
+            env: self.env.clone().unwrap_or_default(),
+            cwd,
         }))
     }
 
-    fn parse_program(command: &str, targets: &[Target]) -> IResult<(Program, Vec<String>)> {
-        // TODO: Better split for command string
-        const DELIM: char = ' ';
-
-        let (cmd, args) = if let Some((program, rest)) = command.split_once(DELIM) {
-            (program, rest.split(DELIM).map(|x| x.to_string()).collect())
-        } else {
-            (command, Vec::new())
-        };
-        
+    fn parse_program(cmd: &str, targets: &[Target]) -> Program {
         let mut target_name = String::new();
         let cmd = targets.iter()
             .find(|t| t.name == cmd)
@@ -518,7 +1033,7 @@ impl TomlStep {
             })
             .unwrap_or(cmd);
 
-        Ok((Program::from(cmd), args))      
+        Program::from(cmd)
     }
 }
 
@@ -546,6 +1061,12 @@ impl TomlOptions {
         if let Some(v) = &self.asm_flags {
             opts.asm_flags = v.clone();
         }
+        if let Some(v) = &self.linker {
+            opts.linker = Some(v.clone());
+        }
+        if let Some(v) = self.export_dynamic {
+            opts.export_dynamic = v;
+        }
         if let Some(v) = &self.std {
             opts.std = Std{
                 c: v.c.unwrap_or_default(),
@@ -573,14 +1094,71 @@ impl TomlOptions {
     }
 }
 
+impl TomlProfile {
+    fn to_real(&self, name: &str) -> IResult<Profile> {
+        let mut profile = match name {
+            "dev" => Profile::dev(),
+            "release" => Profile::release(),
+            _ => bail!("unknown profile `{name}`, only `dev` and `release` are supported"),
+        };
+        if let Some(v) = self.debug {
+            profile.debug = v;
+        }
+        if let Some(v) = self.incremental {
+            profile.incremental = v;
+        }
+        if let Some(v) = self.exceptions {
+            profile.exceptions = v;
+        }
+        if let Some(v) = self.opt_level {
+            profile.opt_level = v;
+        }
+        if let Some(v) = self.lto {
+            profile.lto = v;
+        }
+        if let Some(v) = self.rpath {
+            profile.rpath = v;
+        }
+        if let Some(v) = self.reproducible {
+            profile.reproducible = v;
+        }
+        if let Some(v) = self.strip {
+            profile.strip = v;
+        }
+        if let Some(v) = self.split_debuginfo {
+            profile.split_debuginfo = v;
+        }
+        if let Some(v) = &self.linker {
+            profile.linker = Some(v.clone());
+        }
+        Ok(profile)
+    }
+}
+
 impl TomlDependency {
-    fn to_real(&self, name: &str, root: &Path) -> Dependency {
+    fn to_real(&self, name: &str, root: &Path) -> IResult<Dependency> {
         let name: InternedString = name.into();
         match self {
             Self::Simple(..) => unimplemented!("Only path dependencies for now"),
             Self::Detailed(dep) => {
+                if let Some(spec) = &dep.pkg_config {
+                    if dep.path.is_some() {
+                        bail!("dependency `{name}` cannot specify both `path` and `pkg-config`")
+                    }
+                    // the source of a pkg-config dependency is its `.pc` file
+                    let path = pkg_config::find(spec)?;
+                    return Ok(Dependency { 
+                        name, 
+                        source_id: SourceId::new(path), 
+                        pkg_config: Some(spec.clone()), 
+                        static_link: dep.r#static.unwrap_or(false),
+                    });
+                }
+                if dep.r#static.is_some() {
+                    bail!("dependency `{name}` can only set `static` with `pkg-config`")
+                }
                 let path = dep.path.as_deref().expect("Only path dependencies for now");
-                Dependency { name, source_id: SourceId::new(paths::abs(path, root)) }
+                Ok(Dependency { name, source_id: SourceId::new(paths::abs(path, root)), pkg_config: None, static_link: false })
             }
         }
     }
@@ -591,6 +1169,53 @@ impl TomlTargetDependency {
         match self {
             Self::External(v) => v.clone(),
             Self::Local(target) => TargetName::new(package.name(), *target),
+            Self::Detailed { name, .. } => name.to_real(package),
+        }
+    }
+
+    fn whole_archive(&self) -> bool {
+        matches!(self, Self::Detailed { whole_archive: Some(true), .. })
+    }
+}
+
+
+// Whether the platform key of a `platform` table matches the target platform
+fn platform_matches(key: &str, target: &RustcTarget) -> IResult<bool> {
+    let platform: Platform = key.parse()?;
+    Ok(platform.matches(target.target(), target.cfgs()))
+}
+
+
+// Whether `path` has the file name of a dynamic library (`.so`, `.so.1`, `.dylib`, `.dll`)
+fn is_dynamic_library(path: &Path) -> bool {
+    let name = path.file_name().and_then(|v| v.to_str()).unwrap_or_default();
+    name.ends_with(".so") 
+        || name.contains(".so.") 
+        || name.ends_with(".dylib") 
+        || name.ends_with(".dll")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embed_symbols() {
+        let root = std::env::temp_dir().join("ccargo-test-embed-symbols");
+        paths::create_dir_all(&root).unwrap();
+        for name in ["logo.png", "1-font.ttf"] {
+            paths::write(root.join(name), b"").unwrap();
         }
+        let embed = |file: &str, symbol: Option<&str>| {
+            TomlEmbed{file: file.into(), symbol: symbol.map(String::from)}.to_real(&root, "demo")
+        };
+        assert_eq!(embed("logo.png", None).unwrap(), (root.join("logo.png"), "logo_png".to_string()));
+        assert_eq!(embed("1-font.ttf", None).unwrap().1, "_1_font_ttf");
+        assert_eq!(embed("logo.png", Some("LOGO")).unwrap().1, "LOGO");
+        assert!(embed("logo.png", Some("1logo")).is_err());
+        assert!(embed("logo.png", Some("lo-go")).is_err());
+        assert!(embed("missing.bin", None).is_err());
+        drop(std::fs::remove_dir_all(&root));
     }
 }
@@ -44,7 +44,7 @@ mod imp {
     use std::os::unix::process::CommandExt;
 
     pub fn exec_replace(cmd: &mut std::process::Command) -> Result<()> {
-        let error = command.exec();
+        let error = cmd.exec();
         Err(anyhow::Error::from(error).context(format!("could not execute process {:?}", cmd)))
     }
 }
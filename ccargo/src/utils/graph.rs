@@ -136,59 +136,36 @@ impl GraphCycles {
             })
     }
 
-    /// Remove cycles from a graph based on a heuristic and return removed edges.
-    /// The current heuristic used is to remove an edge from the node 
-    /// that has the least number of non-cycle links per cycle
+    /// Remove cycles from a graph and return removed edges.
+    /// All edges between the nodes of each cycle are removed, so every cycle 
+    /// becomes a set of independent nodes and the graph a DAG
     pub fn remove_from_graph<N: Clone, E: Clone>(self, g: &mut Graph<N, E>) -> GraphRemoved<E> {
-        if self.0.is_empty() {
-            return GraphRemoved(Vec::new());
-        }
-    
         let mut removed = Vec::new();
-        let cycles = self.0;
-        
-        // Create bitset for fast `is_cycle` checks
-        let mut is_cycle = BitVec::new();
-        is_cycle.resize(g.nodes.len());    
-        for cycle in cycles.iter() {
-            for c in cycle.iter() {
-                is_cycle.set(*c);
+
+        // Component of each node, so edges into other cycles are kept
+        let mut component = vec![usize::MAX; g.nodes.len()];
+        for (i, cycle) in self.0.iter().enumerate() {
+            for n in cycle.iter() {
+                component[*n] = i;
             }
         }
-        
-        // Remove cycles from graph
-        for cycle in cycles.into_iter() {
-            // Find node with least number of non-cycle links
-            let node = cycle
-                .into_iter()
-                .min_by_key(|n| {
-                    g.graph[*n].iter()
-                        .map(|i| is_cycle.get(*i))
-                        .count()
-                })
-                .unwrap();
-    
-            // Remove link from that node within cycle
-            let (c, e) = if g.graph[node].len() == 1 {
-                // Only one link - just pop
-                let c = g.graph[node].pop().unwrap();
-                let e = g.edges[node].pop().unwrap();
-                (c, e)
-            } else {
-                // Multiple links - find first link that is part of the cycle
-                let index = g.graph[node]
-                    .iter()
-                    .position(|c| is_cycle.get(*c))
-                    .unwrap();
-                let c = g.graph[node].swap_remove(index);
-                let e = g.edges[node].swap_remove(index);
-                (c, e)
-            };
-    
-            // Track which edge was removed
-            removed.push((node, c, e));
+
+        for cycle in self.0.iter() {
+            for &node in cycle.iter() {
+                let mut i = 0;
+                while i < g.graph[node].len() {
+                    let c = g.graph[node][i];
+                    if component[c] == component[node] {
+                        g.graph[node].swap_remove(i);
+                        let e = g.edges[node].swap_remove(i);
+                        removed.push((node, c, e));
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
         }
-    
+
         GraphRemoved(removed)
     }
 
@@ -1,6 +1,7 @@
 use crate::utils::IResult;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use anyhow::Context;
 use filetime::FileTime;
 
@@ -50,6 +51,42 @@ pub fn write_create_all<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) ->
         .with_context(|| format!("failed to write `{}`", path.display()))
 }
 
+/// Writes `contents` to a file only if they differ from the current contents of the file,
+/// so the modification time only changes with the contents. Returns whether the file was written.
+///
+/// The contents are written to a temporary file that replaces `path`, so files written
+/// by several jobs at the same time are never seen partially written.
+pub fn write_if_changed<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> IResult<bool> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let (path, contents) = (path.as_ref(), contents.as_ref());
+    if fs::read(path).is_ok_and(|v| v == contents) {
+        return Ok(false);
+    }
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(format!(".{}-{}.tmp", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+    write(&tmp, contents)?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        drop(fs::remove_file(&tmp));
+        return Err(e).with_context(|| format!("failed to write `{}`", path.display()));
+    }
+    Ok(true)
+}
+
+/// Replaces `link` with a symbolic link to `original` (relative to the directory of `link`),
+/// the file is copied on platforms without symbolic links.
+pub fn symlink(original: impl AsRef<Path>, link: impl AsRef<Path>) -> IResult<()> {
+    let (original, link) = (original.as_ref(), link.as_ref());
+    if link.symlink_metadata().is_ok() {
+        fs::remove_file(link)
+            .with_context(|| format!("failed to remove `{}`", link.display()))?;
+    }
+    #[cfg(unix)]
+    let res = std::os::unix::fs::symlink(original, link);
+    #[cfg(not(unix))]
+    let res = fs::copy(link.with_file_name(original), link).map(|_| ());
+    res.with_context(|| format!("failed to link `{}` to `{}`", link.display(), original.display()))
+}
+
 /// Get the absolute path of a file relative to `cwd`
 pub fn abs<P: AsRef<Path>>(path: P, cwd: &Path) -> PathBuf {
     let path = path.as_ref();
@@ -79,6 +116,29 @@ pub fn normalize(p: impl AsRef<Path>) -> PathBuf {
     out
 }
 
+/// Recursively lists all files inside `dir`, skipping hidden files and directories (e.g. `.git`)
+pub fn walk_files(dir: impl AsRef<Path>) -> IResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = fs::read_dir(&dir)
+            .with_context(|| format!("failed to read directory `{}`", dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
 /// Find the given relative path in any of the directories in `dirs`
 pub fn find_rel<P, I>(path: P, dirs: I) -> Option<PathBuf>
 where
@@ -145,4 +205,24 @@ mod test {
         #[cfg(windows)]
         assert_eq!(PathBuf::from("a/b/e"), normalize("a\\b\\c\\..\\e"));
     }
+
+    #[test]
+    fn write_if_changed_() {
+        let dir = std::env::temp_dir().join("ccargo-test-write-if-changed");
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("a.h");
+        drop(fs::remove_file(&path));
+
+        assert!(write_if_changed(&path, "a").unwrap());
+        let old = FileTime::from_unix_time(1, 0);
+        filetime::set_file_mtime(&path, old).unwrap();
+        assert!(!write_if_changed(&path, "a").unwrap());
+        assert_eq!(mtime(&path).unwrap(), old);
+        assert!(write_if_changed(&path, "b").unwrap());
+        assert_eq!(read_string(&path).unwrap(), "b");
+        assert_ne!(mtime(&path).unwrap(), old);
+        // No temporary files are left behind
+        assert_eq!(walk_files(&dir).unwrap(), [path]);
+        drop(fs::remove_dir_all(&dir));
+    }
 }
@@ -65,25 +65,27 @@ pub struct BinaryReader<'a>(pub &'a [u8]);
 
 impl<'a> BinaryReader<'a> {
     pub fn read_u8(&mut self) -> Option<u8> {
-        let r = self.0[0];
-        self.0 = &self.0[1..];
-        Some(r)
+        Some(self.take(1)?[0])
     }
 
     pub fn read_u32(&mut self) -> Option<u32> {
-        let (ret, rest) = self.0.split_at(4);
-        self.0 = rest;
-        Some(u32::from_le_bytes(ret.try_into().unwrap()))
+        Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
     }
     
     pub fn read_u64(&mut self) -> Option<u64> {
-        let (ret, rest) = self.0.split_at(8);
-        self.0 = rest;
-        Some(u64::from_le_bytes(ret.try_into().unwrap()))
+        Some(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
     }
     
     pub fn read_bytes(&mut self) -> Option<&'a [u8]> {
         let n = self.read_u64()? as usize;
+        self.take(n)
+    }
+
+    // Next `n` bytes, or `None` if the data is truncated
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
         let (ret, rest) = self.0.split_at(n);
         self.0 = rest;
         Some(ret)